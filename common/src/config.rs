@@ -46,6 +46,22 @@ pub struct MinIO {
     pub region: String,
     pub avatar_bucket: String,
     pub file_bucket: String,
+    pub thumbnail_bucket: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Minio,
+    Local,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Storage {
+    pub backend: StorageKind,
+    pub local_root: String,
+    // Where file-service serves the local backend's part upload and download routes
+    pub local_url: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,6 +72,47 @@ pub struct Jwt {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Upload {
     pub part_exp_min: u64,
+    pub session_ttl_min: u64,
+    pub reap_interval_min: u64,
+    pub hash_retry_min: u64,
+    pub direct_max_mb: u64,
+    pub part_size_mb: u64,
+    pub url_window: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Gc {
+    pub interval_min: u64,
+    pub grace_min: u64,
+    pub batch_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Thumbnail {
+    pub sizes: Vec<u32>,
+    // Larger images are left without thumbnails
+    pub max_source_mb: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Version {
+    // Earlier versions kept per item unless the user chose otherwise
+    pub retention: u64,
+    pub max_retention: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Trash {
+    // Deleted items are purged for good after this many days
+    pub retention_days: u64,
+    pub purge_interval_min: u64,
+    pub batch_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Copy {
+    // A running job whose progress has not moved for this long lost its process, it is failed
+    pub stale_min: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -76,8 +133,14 @@ pub struct Config {
     pub nacos: Nacos,
     pub openapi: OpenApi,
     pub minio: MinIO,
+    pub storage: Storage,
     pub jwt: Jwt,
     pub upload: Upload,
+    pub gc: Gc,
+    pub thumbnail: Thumbnail,
+    pub version: Version,
+    pub trash: Trash,
+    pub copy: Copy,
     pub page: Page,
     pub sonyflake: Sonyflake,
 }
@@ -136,9 +199,42 @@ impl Default for Config {
                 region: "local".to_string(),
                 avatar_bucket: "avatar".to_string(),
                 file_bucket: "file".to_string(),
+                thumbnail_bucket: "thumbnail".to_string(),
+            },
+            storage: Storage {
+                backend: StorageKind::Minio,
+                local_root: "./storage".to_string(),
+                local_url: "http://localhost:8002/api/file/storage".to_string(),
             },
             jwt: Jwt { exp_min: 600 },
-            upload: Upload { part_exp_min: 10 },
+            upload: Upload {
+                part_exp_min: 10,
+                session_ttl_min: 1440,
+                reap_interval_min: 30,
+                hash_retry_min: 10,
+                direct_max_mb: 16,
+                part_size_mb: 8,
+                url_window: 100,
+            },
+            gc: Gc {
+                interval_min: 60,
+                grace_min: 1440,
+                batch_size: 100,
+            },
+            thumbnail: Thumbnail {
+                sizes: vec![128, 256, 512],
+                max_source_mb: 50,
+            },
+            version: Version {
+                retention: 10,
+                max_retention: 100,
+            },
+            trash: Trash {
+                retention_days: 30,
+                purge_interval_min: 60,
+                batch_size: 100,
+            },
+            copy: Copy { stale_min: 30 },
             page: Page { size: 10 },
             sonyflake: Sonyflake { id: 1 },
             nacos: Nacos {
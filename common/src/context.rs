@@ -1,8 +1,7 @@
 use crate::config::Config;
 use crate::util::database::init_rbpool;
-use crate::util::minio::generate_client;
+use crate::util::storage::{generate_storage, StorageBackend};
 use argon2::{Algorithm, Argon2, Params, Version};
-use aws_sdk_s3::Client;
 use rbatis::RBatis;
 use reqwest;
 use std::sync::{LazyLock, OnceLock};
@@ -12,7 +11,7 @@ pub static CONTEXT: LazyLock<ServiceContext> = LazyLock::new(|| ServiceContext::
 pub struct ServiceContext {
     pub rb: RBatis,
     pub config: Config,
-    pub minio_client: OnceLock<Client>,
+    pub storage: OnceLock<Box<dyn StorageBackend>>,
     pub argon2: Argon2<'static>,
     pub req_client: reqwest::Client,
 }
@@ -22,13 +21,18 @@ impl ServiceContext {
         init_rbpool(&self.config, &self.rb).await;
     }
 
-    pub async fn init_minio(&self) {
-        let client = generate_client(&self.config).await;
-        self.minio_client.set(client).unwrap();
+    pub async fn init_storage(&self) {
+        let storage = generate_storage(&self.config).await;
+        if self.storage.set(storage).is_err() {
+            panic!("Storage already initialized");
+        }
     }
 
-    pub fn get_minio(&self) -> &Client {
-        self.minio_client.get().expect("MinIO not initialized")
+    pub fn get_storage(&self) -> &dyn StorageBackend {
+        self.storage
+            .get()
+            .expect("Storage not initialized")
+            .as_ref()
     }
 }
 
@@ -41,7 +45,7 @@ impl Default for ServiceContext {
                 rb
             },
             config,
-            minio_client: OnceLock::new(),
+            storage: OnceLock::new(),
             argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default()),
             req_client: reqwest::Client::builder()
                 .no_gzip()
@@ -68,9 +72,9 @@ macro_rules! argon2_client {
 }
 
 #[macro_export]
-macro_rules! minio_client {
+macro_rules! storage {
     () => {
-        &$crate::context::CONTEXT.minio_client.get().unwrap()
+        $crate::context::CONTEXT.get_storage()
     };
 }
 
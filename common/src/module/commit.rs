@@ -1,4 +1,5 @@
 use crate::module::error::AppError;
+use crate::util::cursor::{decode_cursor, keyset_filter, CursorPage, KeyColumn};
 use chrono::{DateTime, Utc};
 use rbatis::{impl_insert, impl_select_page, RBatis};
 use salvo::oapi::ToSchema;
@@ -39,6 +40,45 @@ impl Commit {
         .await?;
         Ok(())
     }
+
+    // Oldest first so a thread reads top down, an empty cursor starts from the top
+    pub async fn select_after_by_shareid(
+        rb: &RBatis,
+        cursor: &str,
+        limit: u64,
+        share_id: &Uuid,
+    ) -> Result<CursorPage<Commit>, AppError> {
+        let columns = [
+            KeyColumn::new("coalesce(create_time, '-infinity')", "timestamptz", false),
+            KeyColumn::new("id", "uuid", false),
+        ];
+        let mut args = vec![rbs::to_value!(share_id)];
+        let mut filter = String::new();
+        if !cursor.is_empty() {
+            let keys = decode_cursor(cursor, "commit", columns.len())?;
+            let (after, after_args) = keyset_filter(&columns, &keys);
+            filter = format!(" and {}", after);
+            args.extend(after_args);
+        }
+        args.push(rbs::to_value!(limit + 1));
+        let records: Vec<Commit> = rb
+            .query_decode(
+                &format!(
+                    "select * from \"commit\" where share_id = ? and delete_flag = 0{} order by coalesce(create_time, '-infinity'), id limit ?",
+                    filter
+                ),
+                args,
+            )
+            .await?;
+        Ok(CursorPage::from_rows(records, limit, "commit", |last| {
+            vec![
+                last.create_time
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or("-infinity".to_string()),
+                last.id.map(|id| id.to_string()).unwrap_or_default(),
+            ]
+        }))
+    }
 }
 
 impl_insert!(Commit {}, "\"commit\"");
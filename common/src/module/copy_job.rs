@@ -0,0 +1,95 @@
+use crate::module::error::AppError;
+use chrono::{DateTime, Utc};
+use rbatis::{impl_insert, impl_select, RBatis};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum CopyStatus {
+    Running = 0,
+    Completed = 1,
+    Failed = 2,
+}
+
+// A background copy, total counts every item of every copied tree
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct CopyJob {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub update_time: Option<DateTime<Utc>>,
+    pub delete_flag: Option<i8>,
+    pub user_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub total: Option<i64>,
+    pub copied: Option<i64>,
+    pub status: Option<i8>,
+    pub message: Option<String>,
+}
+
+impl CopyJob {
+    pub fn new(user_id: Uuid, parent_id: Option<Uuid>, total: i64) -> Self {
+        CopyJob {
+            id: Some(Uuid::new_v4()),
+            create_time: Some(Utc::now()),
+            update_time: Some(Utc::now()),
+            delete_flag: Some(0),
+            user_id: Some(user_id),
+            parent_id,
+            total: Some(total),
+            copied: Some(0),
+            status: Some(CopyStatus::Running as i8),
+            message: None,
+        }
+    }
+}
+
+impl CopyJob {
+    pub async fn update_copied_by_id(rb: &RBatis, id: &Uuid, copied: i64) -> Result<(), AppError> {
+        rb.exec(
+            "UPDATE \"copy_job\" SET copied = ?, update_time = now() WHERE id = ? AND delete_flag = 0",
+            vec![rbs::to_value!(copied), rbs::to_value!(id)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn finish_by_id(
+        rb: &RBatis,
+        id: &Uuid,
+        status: CopyStatus,
+        copied: i64,
+        message: Option<String>,
+    ) -> Result<(), AppError> {
+        rb.exec(
+            "UPDATE \"copy_job\" SET status = ?, copied = ?, message = ?, update_time = now() WHERE id = ? AND delete_flag = 0",
+            vec![
+                rbs::to_value!(status as i8),
+                rbs::to_value!(copied),
+                rbs::to_value!(message),
+                rbs::to_value!(id),
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Jobs whose process went away, copied keeps the last progress written
+    pub async fn fail_stale(rb: &RBatis, stale_min: i64) -> Result<u64, AppError> {
+        Ok(rb
+            .exec(
+                "UPDATE \"copy_job\" SET status = ?, message = ?, update_time = now() WHERE status = ? AND delete_flag = 0 AND update_time < now() - make_interval(mins => ?::int)",
+                vec![
+                    rbs::to_value!(CopyStatus::Failed as i8),
+                    rbs::to_value!("Interrupted, the items copied so far remain"),
+                    rbs::to_value!(CopyStatus::Running as i8),
+                    rbs::to_value!(stale_min),
+                ],
+            )
+            .await?
+            .rows_affected)
+    }
+}
+
+impl_insert!(CopyJob {}, "\"copy_job\"");
+impl_select!(CopyJob {select_by_id_userid(id: &Uuid, user_id: &Uuid) => "`where id = #{id} and user_id = #{user_id} and delete_flag = 0 limit 1`"}, "\"copy_job\"");
@@ -77,6 +77,36 @@ pub enum AppError {
 
     #[error("Storage limit reached")]
     UserOutSize,
+
+    #[error("Upload session not exists")]
+    UploadSessionNotExists,
+
+    #[error("Hash challenge not passed")]
+    HashChallengeMismatch,
+
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    #[error("File too large")]
+    FileTooLarge,
+
+    #[error("Uploaded parts do not match the upload plan")]
+    UploadPlanMismatch,
+
+    #[error("Part checksum mismatch")]
+    ChecksumMismatch,
+
+    #[error("An item with this name already exists here")]
+    ItemNameConflict,
+
+    #[error("Target folder does not exist or cannot hold this item")]
+    InvalidParent,
+
+    #[error("Cursor is malformed or belongs to another ordering")]
+    InvalidCursor,
+
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
 }
 impl From<PasswordHashError> for AppError {
     fn from(err: PasswordHashError) -> Self {
@@ -96,6 +126,12 @@ impl<T> From<SdkError<T, HttpResponse>> for AppError {
     }
 }
 
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        AppError::StorageError(error.to_string())
+    }
+}
+
 impl From<reqwest::Error> for AppError {
     fn from(error: reqwest::Error) -> Self {
         AppError::InnerError(error.to_string())
@@ -217,6 +253,56 @@ impl Writer for AppError {
                 ResultCode::UserOutSize,
                 format!("{}", self.to_string()),
             ),
+            AppError::UploadSessionNotExists => (
+                StatusCode::NOT_FOUND,
+                ResultCode::UploadSessionNotExists,
+                self.to_string(),
+            ),
+            AppError::HashChallengeMismatch => (
+                StatusCode::UNAUTHORIZED,
+                ResultCode::HashChallengeMismatch,
+                self.to_string(),
+            ),
+            AppError::StorageError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ResultCode::StorageError,
+                self.to_string(),
+            ),
+            AppError::FileTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ResultCode::FileTooLarge,
+                self.to_string(),
+            ),
+            AppError::UploadPlanMismatch => (
+                StatusCode::BAD_REQUEST,
+                ResultCode::UploadPlanMismatch,
+                self.to_string(),
+            ),
+            AppError::ChecksumMismatch => (
+                StatusCode::BAD_REQUEST,
+                ResultCode::ChecksumMismatch,
+                self.to_string(),
+            ),
+            AppError::ItemNameConflict => (
+                StatusCode::CONFLICT,
+                ResultCode::ItemNameConflict,
+                self.to_string(),
+            ),
+            AppError::InvalidParent => (
+                StatusCode::BAD_REQUEST,
+                ResultCode::InvalidParent,
+                self.to_string(),
+            ),
+            AppError::InvalidCursor => (
+                StatusCode::BAD_REQUEST,
+                ResultCode::InvalidCursor,
+                self.to_string(),
+            ),
+            AppError::InvalidValue(_) => (
+                StatusCode::BAD_REQUEST,
+                ResultCode::InvalidValue,
+                self.to_string(),
+            ),
         };
         res.status_code(code);
         res.render(Json(ResultData::new(message, None::<()>, status_code)));
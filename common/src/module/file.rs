@@ -1,10 +1,18 @@
 use crate::module::error::AppError;
 use chrono::{DateTime, Utc};
+use rbatis::executor::Executor;
 use rbatis::{impl_insert, impl_select, RBatis};
 use salvo::oapi::ToSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+// A file is still referenced while a live item, a retained version, a running upload or an
+// unexpired share on one of its items points at it
+const UNREFERENCED: &str = "not exists (select 1 from \"item\" i where i.file_id = f.id and (i.delete_flag = 0 or i.trash_id is not null)) \
+    and not exists (select 1 from \"item_version\" v where v.file_id = f.id and v.delete_flag = 0) \
+    and not exists (select 1 from \"upload_session\" u where u.file_id = f.id and u.status = 0 and u.delete_flag = 0) \
+    and not exists (select 1 from \"share\" s inner join \"item\" i on s.item_id = i.id where i.file_id = f.id and s.delete_flag = 0 and s.timeout_time > now())";
+
 #[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
 pub struct File {
     pub id: Option<Uuid>,
@@ -53,7 +61,7 @@ impl File {
 
 impl File {
     pub async fn update_thumbnail(
-        rb: &RBatis,
+        rb: &dyn Executor,
         id: &Uuid,
         thumbnail_path: &String,
     ) -> Result<(), AppError> {
@@ -70,8 +78,26 @@ impl File {
         Ok(())
     }
 
+    pub async fn update_file_type(
+        rb: &dyn Executor,
+        id: &Uuid,
+        file_type: &String,
+    ) -> Result<(), AppError> {
+        let result: u64 = rb
+            .exec(
+                "UPDATE \"file\" SET file_type = ? WHERE id = ? AND delete_flag = 0",
+                vec![rbs::to_value!(file_type), rbs::to_value!(id)],
+            )
+            .await?
+            .rows_affected;
+        (result == 0)
+            .then_some(Err::<(), AppError>(AppError::FileNotExists))
+            .transpose()?;
+        Ok(())
+    }
+
     pub async fn update_size_sha_256(
-        rb: &RBatis,
+        rb: &dyn Executor,
         id: &Uuid,
         size: i64,
         hash: &String,
@@ -93,7 +119,7 @@ impl File {
         Ok(())
     }
 
-    pub async fn delete_by_id(rb: &RBatis, id: &Uuid) -> Result<(), AppError> {
+    pub async fn delete_by_id(rb: &dyn Executor, id: &Uuid) -> Result<(), AppError> {
         rb.exec(
             "update \"file\" set delete_flag = 1 where id = ?",
             vec![rbs::to_value!(id)],
@@ -101,6 +127,90 @@ impl File {
         .await?;
         Ok(())
     }
+
+    pub async fn select_unreferenced(
+        rb: &RBatis,
+        grace_min: i64,
+        limit: i64,
+    ) -> Result<Vec<File>, AppError> {
+        let files: Vec<File> = rb
+            .query_decode(
+                format!(
+                    "select f.* from \"file\" f where f.create_time < now() - ? * interval '1 minute' and {} limit ?",
+                    UNREFERENCED
+                )
+                .as_str(),
+                vec![rbs::to_value!(grace_min), rbs::to_value!(limit)],
+            )
+            .await?;
+        Ok(files)
+    }
+
+    // Hashing found a size other than the declared one. Every item and version holding the file
+    // was charged the declared size, as were the trash rows over them, so all move by delta
+    pub async fn settle_size_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        delta: i64,
+    ) -> Result<(), AppError> {
+        if delta == 0 {
+            return Ok(());
+        }
+        rb.exec(
+            "update \"user\" u set total_size = u.total_size + ? * c.n, tree_stamp = u.tree_stamp + 1 from (select r.user_id, count(1) as n from \
+            (select user_id from \"item\" where file_id = ? and (delete_flag = 0 or trash_id is not null) \
+            union all select user_id from \"item_version\" where file_id = ? and delete_flag = 0) r group by r.user_id) c \
+            where u.id = c.user_id",
+            vec![rbs::to_value!(delta), rbs::to_value!(id), rbs::to_value!(id)],
+        )
+        .await?;
+        rb.exec(
+            "update \"trash\" t set size = t.size + ? * c.n from (select r.trash_id, count(1) as n from \
+            (select trash_id from \"item\" where file_id = ? and trash_id is not null \
+            union all select i.trash_id from \"item_version\" v inner join \"item\" i on v.item_id = i.id where v.file_id = ? and v.delete_flag = 0 and i.trash_id is not null) r \
+            group by r.trash_id) c where t.id = c.trash_id and t.delete_flag = 0",
+            vec![rbs::to_value!(delta), rbs::to_value!(id), rbs::to_value!(id)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Runs in the transaction that attaches the file, GC cannot take the row until it commits.
+    // A row GC already removed comes back as FileNotExists
+    pub async fn select_for_share(rb: &dyn Executor, id: &Uuid) -> Result<File, AppError> {
+        let value = rb
+            .query(
+                "select * from \"file\" where id = ? and delete_flag = 0 for share",
+                vec![rbs::to_value!(id)],
+            )
+            .await?;
+        let files: Vec<File> = rbatis::decode(value)?;
+        files.into_iter().next().ok_or(AppError::FileNotExists)
+    }
+
+    // False when the row is gone or an attach holds it, GC leaves it for the next round
+    pub async fn lock_for_delete(rb: &dyn Executor, id: &Uuid) -> Result<bool, AppError> {
+        let value = rb
+            .query(
+                "select * from \"file\" where id = ? for update skip locked",
+                vec![rbs::to_value!(id)],
+            )
+            .await?;
+        let files: Vec<File> = rbatis::decode(value)?;
+        Ok(!files.is_empty())
+    }
+
+    // A separate statement after lock_for_delete, so it sees every reference committed before the lock
+    pub async fn remove_unreferenced_by_id(rb: &dyn Executor, id: &Uuid) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                format!("delete from \"file\" f where f.id = ? and {}", UNREFERENCED).as_str(),
+                vec![rbs::to_value!(id)],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
 }
 
 impl_insert!(File {}, "\"file\"");
@@ -1,8 +1,12 @@
 use crate::module::error::AppError;
+use crate::util::cursor::{decode_cursor, keyset_filter, CursorPage, KeyColumn};
+use crate::util::path::{numbered_name, path_segments};
 use chrono::{DateTime, Utc};
-use rbatis::{impl_insert, impl_select, impl_select_page, RBatis};
+use rbatis::executor::Executor;
+use rbatis::{impl_insert, impl_select, Page, PageRequest, RBatis};
 use salvo::oapi::ToSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
@@ -16,8 +20,243 @@ pub struct Item {
     pub is_folder: Option<bool>,
     pub logic_name: Option<String>,
     pub uploaded: Option<bool>,
+    // Set while the item sits in the trash, cleared again on restore or purge
+    pub trash_id: Option<Uuid>,
 }
 
+// Listing row, the item joined with what its file knows
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct ItemVo {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub user_id: Option<Uuid>,
+    pub file_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub is_folder: Option<bool>,
+    pub logic_name: Option<String>,
+    pub uploaded: Option<bool>,
+    pub file_type: Option<String>,
+    // A file's own size, for folders everything below them at any depth
+    pub size: Option<i64>,
+    // Fetch it from the thumbnail endpoint
+    pub has_thumbnail: Option<bool>,
+    // Folders only
+    pub file_count: Option<i64>,
+    pub folder_count: Option<i64>,
+    // Search hits only, "/a/b/name" from the root
+    pub path: Option<String>,
+}
+
+#[derive(ToSchema, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemSort {
+    #[default]
+    Name,
+    // Files only, folders stay grouped first and keep name order
+    Size,
+    CreateTime,
+    Type,
+}
+
+#[derive(ToSchema, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ItemOrder {
+    pub sort: ItemSort,
+    pub order: SortOrder,
+    pub folders_first: bool,
+}
+
+impl Default for ItemOrder {
+    fn default() -> Self {
+        ItemOrder {
+            sort: ItemSort::Name,
+            order: SortOrder::Asc,
+            folders_first: true,
+        }
+    }
+}
+
+impl ItemOrder {
+    // A folder's size is only known after the page was read
+    fn groups_folders(self) -> bool {
+        self.folders_first || self.sort == ItemSort::Size
+    }
+
+    // Name and id break ties so pages do not shift between requests. Nulls fold into
+    // sentinels that sort first ascending, which keeps keyset comparisons total
+    fn key_columns(self) -> Vec<KeyColumn> {
+        let desc = self.order == SortOrder::Desc;
+        let mut columns = Vec::with_capacity(4);
+        if self.groups_folders() {
+            columns.push(KeyColumn::new(
+                "coalesce(i.is_folder, false)",
+                "boolean",
+                true,
+            ));
+        }
+        columns.push(match self.sort {
+            ItemSort::Name => KeyColumn::new("coalesce(i.logic_name, '')", "text", desc),
+            ItemSort::Size => KeyColumn::new(
+                "case when i.is_folder then -1 else coalesce(f.size, -1) end",
+                "bigint",
+                desc,
+            ),
+            ItemSort::CreateTime => {
+                KeyColumn::new("coalesce(i.create_time, '-infinity')", "timestamptz", desc)
+            }
+            ItemSort::Type => KeyColumn::new("coalesce(f.file_type, '')", "text", desc),
+        });
+        columns.push(KeyColumn::new("coalesce(i.logic_name, '')", "text", false));
+        columns.push(KeyColumn::new("i.id", "uuid", false));
+        columns
+    }
+
+    // The same values key_columns reads, taken from the row a page ended on
+    fn key_values(self, item: &ItemVo) -> Vec<String> {
+        let name = item.logic_name.clone().unwrap_or_default();
+        let mut keys = Vec::with_capacity(4);
+        if self.groups_folders() {
+            keys.push(item.is_folder.unwrap_or(false).to_string());
+        }
+        keys.push(match self.sort {
+            ItemSort::Name => name.clone(),
+            ItemSort::Size if item.is_folder == Some(true) => "-1".to_string(),
+            ItemSort::Size => item.size.unwrap_or(-1).to_string(),
+            ItemSort::CreateTime => item
+                .create_time
+                .map(|time| time.to_rfc3339())
+                .unwrap_or("-infinity".to_string()),
+            ItemSort::Type => item.file_type.clone().unwrap_or_default(),
+        });
+        keys.push(name);
+        keys.push(item.id.map(|id| id.to_string()).unwrap_or_default());
+        keys
+    }
+
+    fn tag(self) -> String {
+        format!("{:?}:{:?}:{}", self.sort, self.order, self.groups_folders())
+    }
+
+    fn to_sql(self) -> String {
+        self.key_columns()
+            .iter()
+            .map(|column| {
+                let direction = if column.desc { "desc" } else { "asc" };
+                format!("{} {}", column.expr, direction)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(ToSchema, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Substring,
+    Prefix,
+    // Trigram similarity, best matches first. Substring matching without pg_trgm
+    Fuzzy,
+}
+
+// Every filter is optional, an empty search lists the whole drive
+#[derive(Clone, Debug, Default)]
+pub struct ItemSearch {
+    pub query: Option<String>,
+    pub mode: SearchMode,
+    pub is_folder: Option<bool>,
+    // Matched as a prefix, so "image/" finds every image
+    pub file_type: Option<String>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // Only below this folder
+    pub root_id: Option<Uuid>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ItemPath {
+    id: Option<Uuid>,
+    path: Option<String>,
+}
+
+// What an item holds at any depth, the item itself counted as a file or not at all
+#[derive(ToSchema, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FolderStats {
+    pub id: Option<Uuid>,
+    pub size: i64,
+    pub file_count: i64,
+    pub folder_count: i64,
+}
+
+// What to do when the folder already holds an item with the requested name
+#[derive(ToSchema, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Fail,
+    Rename,
+    Overwrite,
+}
+
+// How many items a subtree holds and the size of their current files
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TreeSize {
+    pub count: i64,
+    pub size: i64,
+}
+
+// One step of the way from the root down to an item
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct Breadcrumb {
+    pub id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub logic_name: Option<String>,
+    pub is_folder: Option<bool>,
+}
+
+// A name still free under the folder, or the item an overwrite lands on
+pub enum NameSlot {
+    Free(String),
+    Taken(Item),
+}
+
+impl ConflictPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "fail" => Some(ConflictPolicy::Fail),
+            "rename" => Some(ConflictPolicy::Rename),
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            _ => None,
+        }
+    }
+}
+
+// Gives up auto-renaming past "name (n)" for this many n
+const MAX_RENAME: u32 = 1000;
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// ItemVo as the listing queries select it
+const VO_COLUMNS: &str = "i.id, i.create_time, i.user_id, i.file_id, i.parent_id, i.is_folder, i.logic_name, i.uploaded, f.file_type, f.size, f.thumbnail is not null as has_thumbnail";
+
+// Live items from the root down, any depth. Union drops ids already walked, so a parent loop left
+// by older moves ends the walk instead of spinning
+const TREE: &str = "with recursive tree as (select id from \"item\" where id = ? and user_id = ? and delete_flag = 0 \
+    union select i.id from \"item\" i inner join tree t on i.parent_id = t.id where i.delete_flag = 0)";
+
 impl Item {
     pub fn new(
         user_id: Uuid,
@@ -37,6 +276,7 @@ impl Item {
             is_folder: Some(is_folder),
             logic_name: Some(logic_name),
             uploaded: Some(uploaded),
+            trash_id: None,
         }
     }
     pub fn set_uploaded(&self, uploaded: bool) -> Item {
@@ -60,7 +300,11 @@ impl Item {
             .transpose()?;
         Ok(())
     }
-    pub async fn delete_by_id(rb: &RBatis, id: &Uuid, user_id: &Uuid) -> Result<(), AppError> {
+    pub async fn delete_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<(), AppError> {
         rb.exec(
             "update \"item\" set delete_flag = 1 where id = ? and user_id = ?",
             vec![rbs::to_value!(id), rbs::to_value!(user_id)],
@@ -69,17 +313,160 @@ impl Item {
         Ok(())
     }
 
-    pub async fn update_parent_by_id(
-        rb: &RBatis,
+    // Swap the current file, only if it is still the one the caller saw
+    pub async fn update_file_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+        from_file_id: &Uuid,
+        file_id: &Uuid,
+    ) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                "update \"item\" set file_id = ? where id = ? and user_id = ? and file_id = ? and delete_flag = 0",
+                vec![
+                    rbs::to_value!(file_id),
+                    rbs::to_value!(id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(from_file_id),
+                ],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
+
+    // Size of the files a subtree holds, retained versions included
+    pub async fn sum_size_with_tree_by_id(
+        rb: &dyn Executor,
         id: &Uuid,
         user_id: &Uuid,
+    ) -> Result<i64, AppError> {
+        let value = rb
+            .query(
+                &format!(
+                    "{} select ((select coalesce(sum(f.size), 0) from \"item\" i inner join \"file\" f on i.file_id = f.id where i.id in (select id from tree)) \
+                    + (select coalesce(sum(v.size), 0) from \"item_version\" v where v.item_id in (select id from tree) and v.delete_flag = 0))::bigint",
+                    TREE
+                ),
+                vec![rbs::to_value!(id), rbs::to_value!(user_id)],
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    pub async fn sum_tree_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<TreeSize, AppError> {
+        let value = rb
+            .query(
+                &format!(
+                    "{} select count(1)::bigint as count, coalesce(sum(f.size), 0)::bigint as size from tree t inner join \"item\" i on t.id = i.id left join \"file\" f on i.file_id = f.id",
+                    TREE
+                ),
+                vec![rbs::to_value!(id), rbs::to_value!(user_id)],
+            )
+            .await?;
+        let sizes: Vec<TreeSize> = rbatis::decode(value)?;
+        Ok(sizes.into_iter().next().unwrap_or_default())
+    }
+
+    // One row per live root, the roots are walked together in one query
+    pub async fn select_stats_by_ids(
+        rb: &dyn Executor,
+        ids: &[Uuid],
+        user_id: &Uuid,
+    ) -> Result<Vec<FolderStats>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let mut args: Vec<rbs::Value> = ids.iter().map(|id| rbs::to_value!(id)).collect();
+        args.push(rbs::to_value!(user_id));
+        let value = rb
+            .query(
+                &format!(
+                    "with recursive tree as (select id as root, id from \"item\" where id in ({}) and user_id = ? and delete_flag = 0 \
+                    union select t.root, i.id from \"item\" i inner join tree t on i.parent_id = t.id where i.delete_flag = 0) \
+                    select t.root as id, coalesce(sum(f.size), 0)::bigint as size, \
+                    count(1) filter (where i.is_folder = false)::bigint as file_count, \
+                    count(1) filter (where i.is_folder = true and t.id <> t.root)::bigint as folder_count \
+                    from tree t inner join \"item\" i on t.id = i.id left join \"file\" f on i.file_id = f.id group by t.root",
+                    placeholders
+                ),
+                args,
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    // False when the root is already gone
+    pub async fn trash_tree_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+        trash_id: &Uuid,
+    ) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                &format!(
+                    "{} update \"item\" set delete_flag = 1, trash_id = ? where id in (select id from tree) and delete_flag = 0",
+                    TREE
+                ),
+                vec![
+                    rbs::to_value!(id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(trash_id),
+                ],
+            )
+            .await?
+            .rows_affected;
+        Ok(result > 0)
+    }
+
+    // The root of a trashed tree, still deleted
+    pub async fn select_trashed_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+        trash_id: &Uuid,
+    ) -> Result<Option<Item>, AppError> {
+        let value = rb
+            .query(
+                "select * from \"item\" where id = ? and user_id = ? and trash_id = ? limit 1",
+                vec![
+                    rbs::to_value!(id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(trash_id),
+                ],
+            )
+            .await?;
+        let items: Vec<Item> = rbatis::decode(value)?;
+        Ok(items.into_iter().next())
+    }
+
+    // The whole tree in one statement, the root lands under parent_id as logic_name
+    pub async fn restore_by_trash(
+        rb: &dyn Executor,
+        trash_id: &Uuid,
+        user_id: &Uuid,
+        root_id: &Uuid,
         parent_id: Option<Uuid>,
+        logic_name: &str,
     ) -> Result<(), AppError> {
         rb.exec(
-            "update \"item\" set parent_id = ? where id = ? and user_id = ? and delete_flag = 0",
+            "update \"item\" set delete_flag = 0, trash_id = null, \
+            parent_id = case when id = ? then ?::uuid else parent_id end, \
+            logic_name = case when id = ? then ? else logic_name end \
+            where trash_id = ? and user_id = ?",
             vec![
+                rbs::to_value!(root_id),
                 rbs::to_value!(parent_id),
-                rbs::to_value!(id),
+                rbs::to_value!(root_id),
+                rbs::to_value!(logic_name),
+                rbs::to_value!(trash_id),
                 rbs::to_value!(user_id),
             ],
         )
@@ -87,24 +474,235 @@ impl Item {
         Ok(())
     }
 
-    pub async fn delete_sub_by_id(rb: &RBatis, id: &Uuid, user_id: &Uuid) -> Result<(), AppError> {
+    // The items stay deleted, without a trash their files become unreferenced
+    pub async fn purge_by_trash(rb: &dyn Executor, trash_id: &Uuid) -> Result<(), AppError> {
         rb.exec(
-            "update \"item\" set delete_flag = 1 where parent_id = ? and user_id = ?",
-            vec![rbs::to_value!(id), rbs::to_value!(user_id)],
+            "update \"item\" set trash_id = null where trash_id = ?",
+            vec![rbs::to_value!(trash_id)],
         )
         .await?;
         Ok(())
     }
 
-    pub async fn update_logic_name_by_id(
+    pub async fn select_vo_page_by_parent(
+        rb: &RBatis,
+        page: &PageRequest,
+        parent_id: Option<Uuid>,
+        user_id: &Uuid,
+        order: ItemOrder,
+    ) -> Result<Page<ItemVo>, AppError> {
+        let total: u64 = rb
+            .query_decode(
+                "select count(1) from \"item\" where parent_id is not distinct from ? and user_id = ? and delete_flag = 0",
+                vec![rbs::to_value!(parent_id), rbs::to_value!(user_id)],
+            )
+            .await?;
+        let records: Vec<ItemVo> = rb
+            .query_decode(
+                &format!(
+                    "select {} from \"item\" i left join \"file\" f on i.file_id = f.id where i.parent_id is not distinct from ? and i.user_id = ? and i.delete_flag = 0 order by {} limit ? offset ?",
+                    VO_COLUMNS,
+                    order.to_sql()
+                ),
+                vec![
+                    rbs::to_value!(parent_id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(page.page_size),
+                    rbs::to_value!(page.page_no.saturating_sub(1) * page.page_size),
+                ],
+            )
+            .await?;
+        Ok(Page::new(page.page_no, page.page_size, total, records))
+    }
+
+    // Keyset page of a folder, an empty cursor starts from the top. Reads one row past
+    // limit to learn whether another page follows
+    pub async fn select_vo_after_by_parent(
+        rb: &RBatis,
+        cursor: &str,
+        limit: u64,
+        parent_id: Option<Uuid>,
+        user_id: &Uuid,
+        order: ItemOrder,
+    ) -> Result<CursorPage<ItemVo>, AppError> {
+        let columns = order.key_columns();
+        let tag = order.tag();
+        let mut args = vec![rbs::to_value!(parent_id), rbs::to_value!(user_id)];
+        let mut filter = String::new();
+        if !cursor.is_empty() {
+            let keys = decode_cursor(cursor, &tag, columns.len())?;
+            let (after, after_args) = keyset_filter(&columns, &keys);
+            filter = format!(" and {}", after);
+            args.extend(after_args);
+        }
+        args.push(rbs::to_value!(limit + 1));
+        let records: Vec<ItemVo> = rb
+            .query_decode(
+                &format!(
+                    "select {} from \"item\" i left join \"file\" f on i.file_id = f.id where i.parent_id is not distinct from ? and i.user_id = ? and i.delete_flag = 0{} order by {} limit ?",
+                    VO_COLUMNS,
+                    filter,
+                    order.to_sql()
+                ),
+                args,
+            )
+            .await?;
+        Ok(CursorPage::from_rows(records, limit, &tag, |last| {
+            order.key_values(last)
+        }))
+    }
+
+    // pg_trgm comes from the migration and may be missing where it could not be created
+    pub async fn has_trigram(rb: &RBatis) -> Result<bool, AppError> {
+        Ok(rb
+            .query_decode(
+                "select exists(select 1 from pg_extension where extname = 'pg_trgm')",
+                vec![],
+            )
+            .await?)
+    }
+
+    pub async fn search_vo_page(
         rb: &RBatis,
+        page: &PageRequest,
+        user_id: &Uuid,
+        search: &ItemSearch,
+    ) -> Result<Page<ItemVo>, AppError> {
+        let mut with = String::new();
+        let mut filter = String::from("i.user_id = ? and i.delete_flag = 0");
+        let mut args = Vec::new();
+        if let Some(root_id) = search.root_id {
+            with = TREE.to_string();
+            args.push(rbs::to_value!(root_id));
+            args.push(rbs::to_value!(user_id));
+        }
+        args.push(rbs::to_value!(user_id));
+        if let Some(root_id) = search.root_id {
+            filter.push_str(" and i.id in (select id from tree) and i.id <> ?");
+            args.push(rbs::to_value!(root_id));
+        }
+        let query = search.query.as_deref().filter(|query| !query.is_empty());
+        let mode = match search.mode {
+            SearchMode::Fuzzy if query.is_some() && !Self::has_trigram(rb).await? => {
+                SearchMode::Substring
+            }
+            mode => mode,
+        };
+        if let Some(query) = query {
+            match mode {
+                SearchMode::Substring => {
+                    filter.push_str(" and i.logic_name ilike ? escape '\\'");
+                    args.push(rbs::to_value!(format!("%{}%", escape_like(query))));
+                }
+                SearchMode::Prefix => {
+                    filter.push_str(" and i.logic_name ilike ? escape '\\'");
+                    args.push(rbs::to_value!(format!("{}%", escape_like(query))));
+                }
+                SearchMode::Fuzzy => {
+                    filter.push_str(" and i.logic_name % ?");
+                    args.push(rbs::to_value!(query));
+                }
+            }
+        }
+        if let Some(is_folder) = search.is_folder {
+            filter.push_str(" and i.is_folder = ?");
+            args.push(rbs::to_value!(is_folder));
+        }
+        if let Some(file_type) = &search.file_type {
+            filter.push_str(" and f.file_type like ? escape '\\'");
+            args.push(rbs::to_value!(format!("{}%", escape_like(file_type))));
+        }
+        if let Some(min_size) = search.min_size {
+            filter.push_str(" and f.size >= ?");
+            args.push(rbs::to_value!(min_size));
+        }
+        if let Some(max_size) = search.max_size {
+            filter.push_str(" and f.size <= ?");
+            args.push(rbs::to_value!(max_size));
+        }
+        if let Some(created_after) = search.created_after {
+            filter.push_str(" and i.create_time >= ?::timestamptz");
+            args.push(rbs::to_value!(created_after.to_rfc3339()));
+        }
+        if let Some(created_before) = search.created_before {
+            filter.push_str(" and i.create_time < ?::timestamptz");
+            args.push(rbs::to_value!(created_before.to_rfc3339()));
+        }
+
+        let total: u64 = rb
+            .query_decode(
+                &format!(
+                    "{} select count(1) from \"item\" i left join \"file\" f on i.file_id = f.id where {}",
+                    with, filter
+                ),
+                args.clone(),
+            )
+            .await?;
+        let order = match (mode, query) {
+            (SearchMode::Fuzzy, Some(query)) => {
+                args.push(rbs::to_value!(query));
+                "similarity(i.logic_name, ?) desc, i.id"
+            }
+            _ => "i.logic_name, i.id",
+        };
+        args.push(rbs::to_value!(page.page_size));
+        args.push(rbs::to_value!(
+            page.page_no.saturating_sub(1) * page.page_size
+        ));
+        let mut records: Vec<ItemVo> = rb
+            .query_decode(
+                &format!(
+                    "{} select {} from \"item\" i left join \"file\" f on i.file_id = f.id where {} order by {} limit ? offset ?",
+                    with, VO_COLUMNS, filter, order
+                ),
+                args,
+            )
+            .await?;
+
+        let ids: Vec<Uuid> = records.iter().filter_map(|item| item.id).collect();
+        let paths: HashMap<Uuid, String> = Self::select_paths_by_ids(rb, &ids)
+            .await?
+            .into_iter()
+            .filter_map(|path| Some((path.id?, path.path?)))
+            .collect();
+        for item in records.iter_mut() {
+            item.path = item.id.and_then(|id| paths.get(&id).cloned());
+        }
+        Ok(Page::new(page.page_no, page.page_size, total, records))
+    }
+
+    async fn select_paths_by_ids(rb: &RBatis, ids: &[Uuid]) -> Result<Vec<ItemPath>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let value = rb
+            .query(
+                &format!(
+                    "with recursive up as (select id as hit, id, parent_id, logic_name::text as path from \"item\" where id in ({}) \
+                    union all select up.hit, i.id, i.parent_id, i.logic_name || '/' || up.path from \"item\" i inner join up on i.id = up.parent_id) \
+                    cycle id set is_cycle using route \
+                    select hit as id, '/' || path as path from up where parent_id is null and not is_cycle",
+                    placeholders
+                ),
+                ids.iter().map(|id| rbs::to_value!(id)).collect(),
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    // Parent and name change together, the folder never sees the item under a name it did not claim
+    pub async fn update_place_by_id(
+        rb: &dyn Executor,
         id: &Uuid,
         user_id: &Uuid,
-        logic_name: &String,
+        parent_id: Option<Uuid>,
+        logic_name: &str,
     ) -> Result<(), AppError> {
         rb.exec(
-            "update \"item\" set logic_name = ? where id = ? and user_id = ?",
+            "update \"item\" set parent_id = ?, logic_name = ? where id = ? and user_id = ? and delete_flag = 0",
             vec![
+                rbs::to_value!(parent_id),
                 rbs::to_value!(logic_name),
                 rbs::to_value!(id),
                 rbs::to_value!(user_id),
@@ -113,11 +711,183 @@ impl Item {
         .await?;
         Ok(())
     }
+
+    async fn advisory_lock(tx: &dyn Executor, key: String) -> Result<(), AppError> {
+        tx.query(
+            "select 1 from pg_advisory_xact_lock(hashtext(?))",
+            vec![rbs::to_value!(key)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Holds until the transaction ends, so two writers cannot both find a name free in one folder
+    pub async fn lock_folder(
+        tx: &dyn Executor,
+        user_id: &Uuid,
+        parent_id: Option<Uuid>,
+    ) -> Result<(), AppError> {
+        let key = format!(
+            "{}/{}",
+            user_id,
+            parent_id.map(|id| id.to_string()).unwrap_or_default()
+        );
+        Self::advisory_lock(tx, key).await
+    }
+
+    // One move per user at a time, two crossing moves could otherwise build a cycle together
+    pub async fn lock_moves(tx: &dyn Executor, user_id: &Uuid) -> Result<(), AppError> {
+        Self::advisory_lock(tx, format!("{}/move", user_id)).await
+    }
+
+    // The root always takes items, anything else must be a live folder of the same user
+    pub async fn check_parent(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        parent_id: Option<Uuid>,
+    ) -> Result<(), AppError> {
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        Self::select_by_id_userid(rb, &parent_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .filter(|parent| parent.is_folder == Some(true))
+            .ok_or(AppError::InvalidParent)?;
+        Ok(())
+    }
+
+    // True when target is the item itself or sits anywhere below it
+    pub async fn tree_contains(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+        target: &Uuid,
+    ) -> Result<bool, AppError> {
+        let value = rb
+            .query(
+                &format!("{} select exists(select 1 from tree where id = ?)", TREE),
+                vec![
+                    rbs::to_value!(id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(target),
+                ],
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    pub async fn select_by_parent_name(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        parent_id: Option<Uuid>,
+        logic_name: &str,
+    ) -> Result<Option<Item>, AppError> {
+        let value = rb
+            .query(
+                "select * from \"item\" where parent_id is not distinct from ? and user_id = ? and logic_name = ? and delete_flag = 0 limit 1",
+                vec![
+                    rbs::to_value!(parent_id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(logic_name),
+                ],
+            )
+            .await?;
+        let items: Vec<Item> = rbatis::decode(value)?;
+        Ok(items.into_iter().next())
+    }
+
+    // Walk "/a/b/c" one name at a time from the root, None stands for the root itself
+    pub async fn resolve_path(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        segments: &[String],
+    ) -> Result<Option<Item>, AppError> {
+        let mut current: Option<Item> = None;
+        for segment in segments {
+            let parent_id = match &current {
+                None => None,
+                Some(item) if item.is_folder == Some(true) => item.id,
+                Some(_) => return Err(AppError::PathOrNameError),
+            };
+            current = Some(
+                Self::select_by_parent_name(rb, user_id, parent_id, segment)
+                    .await?
+                    .ok_or(AppError::PathOrNameError)?,
+            );
+        }
+        Ok(current)
+    }
+
+    pub async fn resolve_folder_path(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        path: &str,
+    ) -> Result<Option<Uuid>, AppError> {
+        match Self::resolve_path(rb, user_id, &path_segments(path)?).await? {
+            None => Ok(None),
+            Some(item) if item.is_folder == Some(true) => Ok(item.id),
+            Some(_) => Err(AppError::PathOrNameError),
+        }
+    }
+
+    // Root first, the item itself last
+    pub async fn select_ancestors_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<Vec<Breadcrumb>, AppError> {
+        let value = rb
+            .query(
+                "with recursive up as (select id, parent_id, logic_name, is_folder, 0 as depth from \"item\" where id = ? and user_id = ? and delete_flag = 0 \
+                union all select i.id, i.parent_id, i.logic_name, i.is_folder, up.depth + 1 from \"item\" i inner join up on i.id = up.parent_id where i.delete_flag = 0) \
+                cycle id set is_cycle using route \
+                select id, parent_id, logic_name, is_folder from up where not is_cycle order by depth desc",
+                vec![rbs::to_value!(id), rbs::to_value!(user_id)],
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    // Apply the policy to a name under parent_id, skipping the item being renamed or moved
+    pub async fn resolve_name(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        parent_id: Option<Uuid>,
+        logic_name: &str,
+        policy: ConflictPolicy,
+        exclude: Option<&Uuid>,
+    ) -> Result<NameSlot, AppError> {
+        let other = |item: &Item| exclude.is_none_or(|id| item.id.as_ref() != Some(id));
+        let Some(existing) = Self::select_by_parent_name(rb, user_id, parent_id, logic_name)
+            .await?
+            .filter(other)
+        else {
+            return Ok(NameSlot::Free(logic_name.to_string()));
+        };
+        match policy {
+            ConflictPolicy::Fail => Err(AppError::ItemNameConflict),
+            ConflictPolicy::Overwrite => Ok(NameSlot::Taken(existing)),
+            ConflictPolicy::Rename => {
+                for n in 1..=MAX_RENAME {
+                    let candidate = numbered_name(logic_name, n);
+                    if Self::select_by_parent_name(rb, user_id, parent_id, &candidate)
+                        .await?
+                        .filter(other)
+                        .is_none()
+                    {
+                        return Ok(NameSlot::Free(candidate));
+                    }
+                }
+                Err(AppError::ItemNameConflict)
+            }
+        }
+    }
 }
 
 impl_insert!(Item {}, "\"item\"");
 impl_select!(Item {select_by_id(id: &Uuid) => "`where id = #{id} and delete_flag = 0 limit 1`"}, "\"item\"");
 impl_select!(Item {select_by_id_userid(id: &Uuid, user_id: &Uuid) => "`where id = #{id} and user_id = #{user_id} and delete_flag = 0 limit 1`"}, "\"item\"");
+impl_select!(Item {select_by_parent_userid(parent_id: &Uuid, user_id: &Uuid) => "`where parent_id = #{parent_id} and user_id = #{user_id} and delete_flag = 0`"}, "\"item\"");
 impl_select!(Item {select_path_by_logic_name(logic_name: &String, user_id: &Uuid) => "`where logic_name = #{logic_name} and user_id = #{user_id} and delete_flag = 0 and is_folder = true limit 1`"}, "\"item\"");
-impl_select_page!(Item {select_page_by_parent(id:&Uuid, user_id: &Uuid) => "where parent_id = #{id} and user_id = #{user_id} and delete_flag = 0"}, "\"item\"");
-impl_select_page!(Item {select_page_root(user_id: &Uuid) => "where parent_id is null and user_id = #{user_id} and delete_flag = 0"}, "\"item\"");
@@ -0,0 +1,301 @@
+use crate::config;
+use crate::module::error::AppError;
+use crate::module::file::File;
+use crate::module::item::Item;
+use crate::module::user::User;
+use crate::util::database::begin_tx;
+use chrono::{DateTime, Utc};
+use rbatis::executor::Executor;
+use rbatis::{impl_insert, impl_select, RBatis};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// A file an item held before its current one, charged to the owner's quota while retained
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct ItemVersion {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub delete_flag: Option<i8>,
+    pub user_id: Option<Uuid>,
+    pub item_id: Option<Uuid>,
+    pub file_id: Option<Uuid>,
+    pub size: Option<i64>,
+}
+
+// Listing row, the current file has no version id
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct ItemVersionVo {
+    pub id: Option<Uuid>,
+    pub file_id: Option<Uuid>,
+    pub size: Option<i64>,
+    pub sha_256: Option<String>,
+    pub file_type: Option<String>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub current: bool,
+}
+
+// A retained version and whether its item is live, trashed items keep their history
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetainedVersion {
+    pub id: Option<Uuid>,
+    pub item_id: Option<Uuid>,
+    pub live: Option<bool>,
+}
+
+// Versions past the newest keep of every live item, rows newest first within an item. The
+// history of a trashed item is in trash.size and goes when the trash is purged
+pub fn beyond_retention(rows: &[RetainedVersion], keep: usize) -> Vec<Uuid> {
+    let mut seen: HashMap<Uuid, usize> = HashMap::new();
+    rows.iter()
+        .filter(|version| version.live == Some(true))
+        .filter_map(|version| {
+            let n = seen.entry(version.item_id?).or_default();
+            *n += 1;
+            if *n > keep {
+                version.id
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl ItemVersion {
+    pub fn new(user_id: Uuid, item_id: Uuid, file_id: Uuid, size: i64) -> ItemVersion {
+        ItemVersion {
+            id: Some(Uuid::new_v4()),
+            create_time: Some(Utc::now()),
+            delete_flag: Some(0),
+            user_id: Some(user_id),
+            item_id: Some(item_id),
+            file_id: Some(file_id),
+            size: Some(size),
+        }
+    }
+}
+
+impl ItemVersion {
+    // The current file moves into the history and stays charged until pruned
+    pub async fn push(
+        tx: &dyn Executor,
+        user_id: &Uuid,
+        item_id: &Uuid,
+        file_id: &Uuid,
+    ) -> Result<(), AppError> {
+        let item = Item::select_by_id_userid(tx, item_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .filter(|item| item.is_folder != Some(true))
+            .ok_or(AppError::ItemNotExists)?;
+        let current = item.file_id.ok_or(AppError::FileNotExists)?;
+        let size = File::select_by_id(tx, &current)
+            .await?
+            .first()
+            .and_then(|file| file.size)
+            .unwrap_or_default();
+        ItemVersion::insert(tx, &ItemVersion::new(*user_id, *item_id, current, size)).await?;
+        if !Item::update_file_by_id(tx, item_id, user_id, &current, file_id).await? {
+            return Err(AppError::ItemNotExists);
+        }
+        Ok(())
+    }
+
+    // Drop the oldest versions past the user's retention and give their size back
+    pub async fn prune(rb: &RBatis, user_id: &Uuid, item_id: &Uuid) -> Result<(), AppError> {
+        let user = User::select_by_id(rb, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UserNotExists)?;
+        let keep = user
+            .version_retention
+            .map(|retention| retention.max(0) as i64)
+            .unwrap_or(config!().version.retention as i64);
+        for version in Self::select_beyond_retention(rb, item_id, user_id, keep).await? {
+            let Some(version_id) = version.id else {
+                continue;
+            };
+            let tx = begin_tx(rb).await?;
+            if Self::delete_by_id(&tx, &version_id).await? {
+                User::update_total_size_by_id(&tx, user_id, &-version.size.unwrap_or_default())
+                    .await?;
+            }
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    // Every live item of the user at once, for when the retention shrinks. The flip and the
+    // release are one statement, a version another prune got to first is not released twice
+    pub async fn prune_by_user(
+        rb: &dyn Executor,
+        user_id: &Uuid,
+        keep: usize,
+    ) -> Result<(), AppError> {
+        let value = rb
+            .query(
+                "select v.id, v.item_id, i.delete_flag = 0 as live from \"item_version\" v inner join \"item\" i on v.item_id = i.id \
+                where v.user_id = ? and v.delete_flag = 0 order by v.item_id, v.create_time desc",
+                vec![rbs::to_value!(user_id)],
+            )
+            .await?;
+        let rows: Vec<RetainedVersion> = rbatis::decode(value)?;
+        for ids in beyond_retention(&rows, keep).chunks(500) {
+            let placeholders = vec!["?"; ids.len()].join(", ");
+            let mut args: Vec<rbs::Value> = ids.iter().map(|id| rbs::to_value!(id)).collect();
+            args.push(rbs::to_value!(user_id));
+            rb.exec(
+                &format!(
+                    "with dropped as (update \"item_version\" set delete_flag = 1 where id in ({}) and delete_flag = 0 returning size) \
+                    update \"user\" set total_size = total_size - (select coalesce(sum(size), 0) from dropped) where id = ?",
+                    placeholders
+                ),
+                args,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    // Newest upload first, the current file always leads
+    pub async fn select_vo_by_item(
+        rb: &RBatis,
+        item_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<Vec<ItemVersionVo>, AppError> {
+        Ok(rb
+            .query_decode(
+                "select * from (select null::uuid as id, f.id as file_id, f.size, f.sha_256, f.file_type, f.create_time, true as current from \"item\" i inner join \"file\" f on i.file_id = f.id where i.id = ? and i.user_id = ? and i.delete_flag = 0 \
+                union all select v.id, f.id, v.size, f.sha_256, f.file_type, f.create_time, false from \"item_version\" v inner join \"file\" f on v.file_id = f.id where v.item_id = ? and v.user_id = ? and v.delete_flag = 0) t \
+                order by current desc, create_time desc",
+                vec![
+                    rbs::to_value!(item_id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(item_id),
+                    rbs::to_value!(user_id),
+                ],
+            )
+            .await?)
+    }
+
+    // Versions past the newest keep, oldest last
+    pub async fn select_beyond_retention(
+        rb: &RBatis,
+        item_id: &Uuid,
+        user_id: &Uuid,
+        keep: i64,
+    ) -> Result<Vec<ItemVersion>, AppError> {
+        Ok(rb
+            .query_decode(
+                "select * from \"item_version\" where item_id = ? and user_id = ? and delete_flag = 0 order by create_time desc offset ?",
+                vec![
+                    rbs::to_value!(item_id),
+                    rbs::to_value!(user_id),
+                    rbs::to_value!(keep),
+                ],
+            )
+            .await?)
+    }
+
+    // False when someone else already dropped the version
+    pub async fn delete_by_id(rb: &dyn Executor, id: &Uuid) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                "update \"item_version\" set delete_flag = 1 where id = ? and delete_flag = 0",
+                vec![rbs::to_value!(id)],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
+
+    pub async fn delete_by_trash(rb: &dyn Executor, trash_id: &Uuid) -> Result<(), AppError> {
+        rb.exec(
+            "update \"item_version\" set delete_flag = 1 where item_id in (select id from \"item\" where trash_id = ?) and delete_flag = 0",
+            vec![rbs::to_value!(trash_id)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    // History of a single file item
+    pub async fn sum_size_by_item(
+        rb: &dyn Executor,
+        item_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<i64, AppError> {
+        let value = rb
+            .query(
+                "select coalesce(sum(size), 0)::bigint from \"item_version\" where item_id = ? and user_id = ? and delete_flag = 0",
+                vec![rbs::to_value!(item_id), rbs::to_value!(user_id)],
+            )
+            .await?;
+        Ok(rbatis::decode(value)?)
+    }
+
+    pub async fn delete_by_item(
+        rb: &dyn Executor,
+        item_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<(), AppError> {
+        rb.exec(
+            "update \"item_version\" set delete_flag = 1 where item_id = ? and user_id = ? and delete_flag = 0",
+            vec![rbs::to_value!(item_id), rbs::to_value!(user_id)],
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+impl_insert!(ItemVersion {}, "\"item_version\"");
+impl_select!(ItemVersion {select_by_id_item_userid(id: &Uuid, item_id: &Uuid, user_id: &Uuid) => "`where id = #{id} and item_id = #{item_id} and user_id = #{user_id} and delete_flag = 0 limit 1`"}, "\"item_version\"");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_skips_trashed_items() {
+        let live = Uuid::new_v4();
+        let trashed = Uuid::new_v4();
+        let version = |item_id: Uuid, live: bool, size: i64| {
+            (
+                RetainedVersion {
+                    id: Some(Uuid::new_v4()),
+                    item_id: Some(item_id),
+                    live: Some(live),
+                },
+                size,
+            )
+        };
+        let versions = vec![
+            version(live, true, 10),
+            version(live, true, 20),
+            version(live, true, 30),
+            version(trashed, false, 5),
+            version(trashed, false, 6),
+        ];
+        let rows: Vec<RetainedVersion> = versions.iter().map(|(row, _)| row.clone()).collect();
+        let size_of = |id: &Uuid| {
+            versions
+                .iter()
+                .find(|(row, _)| row.id == Some(*id))
+                .map(|(_, size)| *size)
+                .unwrap()
+        };
+
+        let doomed = beyond_retention(&rows, 1);
+        assert_eq!(doomed, vec![rows[1].id.unwrap(), rows[2].id.unwrap()]);
+
+        // Both current files are 100, the trashed item's share of the quota sits in trash.size
+        let mut total_size = 100 + 100 + 10 + 20 + 30 + 5 + 6;
+        let trash_size = 100 + 5 + 6;
+        total_size -= doomed.iter().map(size_of).sum::<i64>();
+        // Purging the trash releases its size once, the pruned versions are not in it
+        total_size -= trash_size;
+        assert_eq!(total_size, 100 + 10);
+    }
+}
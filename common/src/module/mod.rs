@@ -2,5 +2,9 @@ pub mod user;
 pub mod error;
 pub mod file;
 pub mod item;
+pub mod item_version;
 pub mod share;
-pub mod commit;
\ No newline at end of file
+pub mod trash;
+pub mod commit;
+pub mod upload_session;
+pub mod copy_job;
\ No newline at end of file
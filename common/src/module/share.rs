@@ -1,4 +1,5 @@
 use crate::module::error::AppError;
+use crate::util::cursor::{decode_cursor, keyset_filter, CursorPage, KeyColumn};
 use chrono::{DateTime, Utc};
 use rbatis::{impl_select, RBatis};
 use rbs::from_value;
@@ -114,6 +115,45 @@ impl Share {
         let logic_name: LogicName = from_value(val).map_err(|_| AppError::ShareFileNotFound)?;
         Ok(logic_name.logic_name)
     }
+
+    // Newest first, an empty cursor starts from the top
+    pub async fn select_after_by_userid(
+        rb: &RBatis,
+        cursor: &str,
+        limit: u64,
+        user_id: &Uuid,
+    ) -> Result<CursorPage<Share>, AppError> {
+        let columns = [
+            KeyColumn::new("coalesce(create_time, '-infinity')", "timestamptz", true),
+            KeyColumn::new("id", "uuid", true),
+        ];
+        let mut args = vec![rbs::to_value!(user_id)];
+        let mut filter = String::new();
+        if !cursor.is_empty() {
+            let keys = decode_cursor(cursor, "share", columns.len())?;
+            let (after, after_args) = keyset_filter(&columns, &keys);
+            filter = format!(" and {}", after);
+            args.extend(after_args);
+        }
+        args.push(rbs::to_value!(limit + 1));
+        let records: Vec<Share> = rb
+            .query_decode(
+                &format!(
+                    "select * from \"share\" where user_id = ? and delete_flag = 0{} order by coalesce(create_time, '-infinity') desc, id desc limit ?",
+                    filter
+                ),
+                args,
+            )
+            .await?;
+        Ok(CursorPage::from_rows(records, limit, "share", |last| {
+            vec![
+                last.create_time
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or("-infinity".to_string()),
+                last.id.map(|id| id.to_string()).unwrap_or_default(),
+            ]
+        }))
+    }
 }
 
 impl_select!(Share {select_by_id(id: &Uuid) => "`where id = #{id} and delete_flag = 0 limit 1`"}, "\"share\"");
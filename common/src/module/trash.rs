@@ -0,0 +1,86 @@
+use crate::module::error::AppError;
+use chrono::{DateTime, Utc};
+use rbatis::executor::Executor;
+use rbatis::{impl_insert, impl_select, RBatis};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// One deletion, the subtree under item_id keeps its quota charge until purged
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct Trash {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub delete_flag: Option<i8>,
+    pub user_id: Option<Uuid>,
+    pub item_id: Option<Uuid>,
+    pub size: Option<i64>,
+}
+
+// Listing row, the deleted top-level item as it was
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct TrashVo {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub item_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+    pub logic_name: Option<String>,
+    pub is_folder: Option<bool>,
+    pub size: Option<i64>,
+}
+
+impl Trash {
+    pub fn new(user_id: Uuid, item_id: Uuid, size: i64) -> Trash {
+        Trash {
+            id: Some(Uuid::new_v4()),
+            create_time: Some(Utc::now()),
+            delete_flag: Some(0),
+            user_id: Some(user_id),
+            item_id: Some(item_id),
+            size: Some(size),
+        }
+    }
+}
+
+impl Trash {
+    pub async fn select_vo_by_userid(
+        rb: &RBatis,
+        user_id: &Uuid,
+    ) -> Result<Vec<TrashVo>, AppError> {
+        Ok(rb
+            .query_decode(
+                "select t.id, t.create_time, t.item_id, i.parent_id, i.logic_name, i.is_folder, t.size from \"trash\" t inner join \"item\" i on t.item_id = i.id where t.user_id = ? and t.delete_flag = 0 order by t.create_time desc",
+                vec![rbs::to_value!(user_id)],
+            )
+            .await?)
+    }
+
+    pub async fn select_expired(
+        rb: &RBatis,
+        retention_days: i64,
+        limit: i64,
+    ) -> Result<Vec<Trash>, AppError> {
+        Ok(rb
+            .query_decode(
+                "select * from \"trash\" where delete_flag = 0 and create_time < now() - make_interval(days => ?::int) order by create_time limit ?",
+                vec![rbs::to_value!(retention_days), rbs::to_value!(limit)],
+            )
+            .await?)
+    }
+
+    // False when a concurrent restore or purge got there first
+    pub async fn delete_by_id(rb: &dyn Executor, id: &Uuid) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                "update \"trash\" set delete_flag = 1 where id = ? and delete_flag = 0",
+                vec![rbs::to_value!(id)],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
+}
+
+impl_insert!(Trash {}, "\"trash\"");
+impl_select!(Trash {select_by_id_userid(id: &Uuid, user_id: &Uuid) => "`where id = #{id} and user_id = #{user_id} and delete_flag = 0 limit 1`"}, "\"trash\"");
+impl_select!(Trash {select_by_userid(user_id: &Uuid) => "`where user_id = #{user_id} and delete_flag = 0`"}, "\"trash\"");
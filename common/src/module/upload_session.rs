@@ -0,0 +1,121 @@
+use crate::module::error::AppError;
+use crate::util::storage::ChecksumAlgorithm;
+use chrono::{DateTime, Utc};
+use rbatis::executor::Executor;
+use rbatis::{impl_insert, impl_select, RBatis};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum UploadStatus {
+    Uploading = 0,
+    Completed = 1,
+    Aborted = 2,
+    Hashing = 3,
+}
+
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: Option<Uuid>,
+    pub create_time: Option<DateTime<Utc>>,
+    pub update_time: Option<DateTime<Utc>>,
+    pub delete_flag: Option<i8>,
+    pub user_id: Option<Uuid>,
+    pub file_id: Option<Uuid>,
+    pub upload_id: Option<String>,
+    pub path: Option<String>,
+    pub size: Option<i64>,
+    pub part_size: Option<i64>,
+    pub checksum_algorithm: Option<String>,
+    pub status: Option<i8>,
+}
+
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct UploadedPartVo {
+    pub part: i64,
+    pub etag: Option<String>,
+    pub size: Option<i64>,
+}
+
+impl UploadSession {
+    pub fn new(
+        user_id: Uuid,
+        file_id: Uuid,
+        upload_id: String,
+        path: String,
+        size: i64,
+        part_size: i64,
+        checksum_algorithm: Option<ChecksumAlgorithm>,
+    ) -> Self {
+        UploadSession {
+            id: Some(Uuid::new_v4()),
+            create_time: Some(Utc::now()),
+            update_time: Some(Utc::now()),
+            delete_flag: Some(0),
+            user_id: Some(user_id),
+            file_id: Some(file_id),
+            upload_id: Some(upload_id),
+            path: Some(path),
+            size: Some(size),
+            part_size: Some(part_size),
+            checksum_algorithm: checksum_algorithm.map(|a| a.as_str().to_string()),
+            status: Some(UploadStatus::Uploading as i8),
+        }
+    }
+}
+
+impl UploadSession {
+    pub fn checksum_algorithm(&self) -> Option<ChecksumAlgorithm> {
+        self.checksum_algorithm
+            .as_deref()
+            .and_then(ChecksumAlgorithm::parse)
+    }
+
+    pub async fn update_status_by_id_status(
+        rb: &dyn Executor,
+        id: &Uuid,
+        from: UploadStatus,
+        to: UploadStatus,
+    ) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                "UPDATE \"upload_session\" SET status = ?, update_time = now() WHERE id = ? AND status = ? AND delete_flag = 0",
+                vec![
+                    rbs::to_value!(to as i8),
+                    rbs::to_value!(id),
+                    rbs::to_value!(from as i8),
+                ],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
+
+    // Take over a session nobody has touched for stale_min, so only one worker retries it
+    pub async fn claim_stale_by_id(
+        rb: &RBatis,
+        id: &Uuid,
+        status: UploadStatus,
+        stale_min: i64,
+    ) -> Result<bool, AppError> {
+        let result: u64 = rb
+            .exec(
+                "UPDATE \"upload_session\" SET update_time = now() WHERE id = ? AND status = ? AND update_time < now() - ? * interval '1 minute' AND delete_flag = 0",
+                vec![
+                    rbs::to_value!(id),
+                    rbs::to_value!(status as i8),
+                    rbs::to_value!(stale_min),
+                ],
+            )
+            .await?
+            .rows_affected;
+        Ok(result == 1)
+    }
+}
+
+impl_insert!(UploadSession {}, "\"upload_session\"");
+impl_select!(UploadSession {select_by_id_userid(id: &Uuid, user_id: &Uuid) => "`where id = #{id} and user_id = #{user_id} and delete_flag = 0 limit 1`"}, "\"upload_session\"");
+impl_select!(UploadSession {select_by_userid_status(user_id: &Uuid, status: i8) => "`where user_id = #{user_id} and status = #{status} and delete_flag = 0 order by create_time desc`"}, "\"upload_session\"");
+impl_select!(UploadSession {select_expired(status: i8, ttl_min: i64) => "`where status = #{status} and create_time < now() - #{ttl_min} * interval '1 minute' and delete_flag = 0`"}, "\"upload_session\"");
+impl_select!(UploadSession {select_stale(status: i8, stale_min: i64) => "`where status = #{status} and update_time < now() - #{stale_min} * interval '1 minute' and delete_flag = 0`"}, "\"upload_session\"");
@@ -3,6 +3,7 @@ use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
 use chrono::{DateTime, Utc};
+use rbatis::executor::Executor;
 use rbatis::{impl_insert, impl_select, impl_select_page, RBatis};
 use salvo::oapi::ToSchema;
 use serde::{Deserialize, Serialize};
@@ -23,6 +24,7 @@ pub struct User {
     pub create_time: Option<DateTime<Utc>>,
     pub max_size: Option<i64>,
     pub total_size: Option<i64>,
+    pub version_retention: Option<i32>,
 }
 
 #[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
@@ -39,6 +41,7 @@ pub struct UserVo {
     pub create_time: Option<DateTime<Utc>>,
     pub max_size: Option<i64>,
     pub total_size: Option<i64>,
+    pub version_retention: Option<i32>,
 }
 
 impl User {
@@ -72,6 +75,7 @@ impl User {
             delete_flag: Some(0),
             max_size: Some(1073741824),
             total_size: Some(0),
+            version_retention: None,
             create_time: None,
         }
     }
@@ -190,7 +194,7 @@ impl User {
     }
 
     pub async fn update_total_size_by_id(
-        rb: &RBatis,
+        rb: &dyn Executor,
         id: &Uuid,
         size: &i64,
     ) -> Result<(), AppError> {
@@ -202,6 +206,37 @@ impl User {
         Ok(())
     }
 
+    pub async fn reserve_total_size_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        size: &i64,
+    ) -> Result<(), AppError> {
+        let result: u64 = rb
+            .exec(
+                "update \"user\" set total_size = total_size + ? where id = ? and delete_flag = 0 and total_size + ? <= max_size",
+                vec![rbs::to_value!(size), rbs::to_value!(id), rbs::to_value!(size)],
+            )
+            .await?
+            .rows_affected;
+        (result == 0)
+            .then_some(Err::<(), AppError>(AppError::UserOutSize))
+            .transpose()?;
+        Ok(())
+    }
+
+    pub async fn update_version_retention_by_id(
+        rb: &dyn Executor,
+        id: &Uuid,
+        retention: &i32,
+    ) -> Result<(), AppError> {
+        rb.exec(
+            "update \"user\" set version_retention = ? where id = ? and delete_flag = 0",
+            vec![rbs::to_value!(retention), rbs::to_value!(id)],
+        )
+        .await?;
+        Ok(())
+    }
+
     pub async fn update_max_size_by_id(rb: &RBatis, id: &Uuid, size: &i64) -> Result<(), AppError> {
         rb.exec(
             "update \"user\" set max_size = ? where id = ? and delete_flag = 0",
@@ -210,6 +245,16 @@ impl User {
         .await?;
         Ok(())
     }
+
+    // Bumped on every write to the user's items, stats cached under an older stamp are stale
+    pub async fn select_tree_stamp(rb: &RBatis, id: &Uuid) -> Result<i64, AppError> {
+        Ok(rb
+            .query_decode(
+                "select coalesce((select tree_stamp from \"user\" where id = ?), 0)",
+                vec![rbs::to_value!(id)],
+            )
+            .await?)
+    }
 }
 
 impl_insert!(User {}, "\"user\"");
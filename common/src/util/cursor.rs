@@ -0,0 +1,115 @@
+use crate::module::error::AppError;
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+// One keyset column, expr is what the query orders by and cast types the bound value
+#[derive(Clone, Copy, Debug)]
+pub struct KeyColumn {
+    pub expr: &'static str,
+    pub cast: &'static str,
+    pub desc: bool,
+}
+
+impl KeyColumn {
+    pub const fn new(expr: &'static str, cast: &'static str, desc: bool) -> Self {
+        KeyColumn { expr, cast, desc }
+    }
+}
+
+#[derive(ToSchema, Clone, Debug, Serialize, Deserialize)]
+pub struct CursorPage<T> {
+    pub records: Vec<T>,
+    // Absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> CursorPage<T> {
+    // Rows were read with limit + 1, the extra one only says another page follows
+    pub fn from_rows<F>(mut records: Vec<T>, limit: u64, tag: &str, keys: F) -> Self
+    where
+        F: Fn(&T) -> Vec<String>,
+    {
+        let mut next_cursor = None;
+        if records.len() as u64 > limit {
+            records.truncate(limit as usize);
+            next_cursor = records.last().map(|last| encode_cursor(tag, &keys(last)));
+        }
+        CursorPage {
+            records,
+            next_cursor,
+        }
+    }
+}
+
+// "tag.key.key..", every part base64url so names may hold any character. The tag ties a
+// cursor to the ordering that produced it
+pub fn encode_cursor(tag: &str, keys: &[String]) -> String {
+    std::iter::once(tag)
+        .chain(keys.iter().map(String::as_str))
+        .map(|part| BASE64_URL_SAFE_NO_PAD.encode(part))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+pub fn decode_cursor(cursor: &str, tag: &str, len: usize) -> Result<Vec<String>, AppError> {
+    let parts = cursor
+        .split('.')
+        .map(|part| {
+            BASE64_URL_SAFE_NO_PAD
+                .decode(part)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or(AppError::InvalidCursor)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts.split_first() {
+        Some((head, keys)) if head == tag && keys.len() == len => Ok(keys.to_vec()),
+        _ => Err(AppError::InvalidCursor),
+    }
+}
+
+// Rows strictly after the keys in the given column order, spelled out column by column
+// since a row comparison cannot mix directions. Binds come back in placeholder order
+pub fn keyset_filter(columns: &[KeyColumn], keys: &[String]) -> (String, Vec<rbs::Value>) {
+    let mut terms = Vec::with_capacity(columns.len());
+    let mut args = Vec::new();
+    for (n, column) in columns.iter().enumerate() {
+        let mut term = Vec::with_capacity(n + 1);
+        for (equal, key) in columns[..n].iter().zip(keys) {
+            term.push(format!("{} = ?::{}", equal.expr, equal.cast));
+            args.push(rbs::to_value!(key));
+        }
+        let op = if column.desc { "<" } else { ">" };
+        term.push(format!("{} {} ?::{}", column.expr, op, column.cast));
+        args.push(rbs::to_value!(&keys[n]));
+        terms.push(format!("({})", term.join(" and ")));
+    }
+    (format!("({})", terms.join(" or ")), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let keys = vec!["a.b/c".to_string(), "".to_string()];
+        let cursor = encode_cursor("name", &keys);
+        assert_eq!(decode_cursor(&cursor, "name", 2).unwrap(), keys);
+        assert!(decode_cursor(&cursor, "size", 2).is_err());
+        assert!(decode_cursor(&cursor, "name", 3).is_err());
+        assert!(decode_cursor("not base64!", "name", 2).is_err());
+
+        let columns = [
+            KeyColumn::new("t.create_time", "timestamptz", true),
+            KeyColumn::new("t.id", "uuid", false),
+        ];
+        let (filter, args) = keyset_filter(&columns, &keys);
+        assert_eq!(
+            filter,
+            "((t.create_time < ?::timestamptz) or (t.create_time = ?::timestamptz and t.id > ?::uuid))"
+        );
+        assert_eq!(args.len(), 3);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::config::Config;
+use crate::module::error::AppError;
+use rbatis::executor::RBatisTxExecutorGuard;
 use rbatis::RBatis;
 use rbdc_pg::PostgresDriver;
 
@@ -9,3 +11,13 @@ pub async fn init_rbpool(config: &Config, rb: &RBatis) {
     let pool = rb.get_pool().unwrap();
     pool.set_max_open_conns(config.database.pool_size).await;
 }
+
+// Begin a transaction that rolls back on drop unless it was committed
+pub async fn begin_tx(rb: &RBatis) -> Result<RBatisTxExecutorGuard, AppError> {
+    let tx = rb.acquire_begin().await?;
+    Ok(tx.defer_async(|tx| async move {
+        if !tx.done() {
+            let _ = tx.rollback().await;
+        }
+    }))
+}
@@ -0,0 +1,102 @@
+use crate::module::error::AppError;
+use crate::util::mime::SNIFF_LEN;
+use futures::StreamExt;
+use multer::{Constraints, Multipart, SizeLimit};
+use salvo::http::header::CONTENT_TYPE;
+use salvo::Request;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+// Text fields are ids, names and paths
+const FIELD_LIMIT: u64 = 64 * 1024;
+
+// The file part of a form, hashed while it was read and spooled to disk. The spool goes away
+// with the value
+pub struct SpooledFile {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    pub size: i64,
+    pub sha_256: String,
+    // The first SNIFF_LEN bytes, enough to sniff the type
+    pub head: Vec<u8>,
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct StreamedForm {
+    pub fields: HashMap<String, String>,
+    pub file: Option<SpooledFile>,
+}
+
+// Parse a multipart body as it arrives instead of letting salvo spool it first. Only file_field
+// may carry content, at most max_size bytes of it
+pub async fn read_streamed_form(
+    req: &mut Request,
+    file_field: &str,
+    max_size: u64,
+) -> Result<StreamedForm, AppError> {
+    let boundary = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| multer::parse_boundary(v).ok())
+        .ok_or(AppError::MissingField("multipart".into()))?;
+    let body = req
+        .take_body()
+        .map(|frame| frame.map(|frame| frame.into_data().unwrap_or_default()));
+    let limits = SizeLimit::new()
+        .per_field(FIELD_LIMIT)
+        .for_field(file_field.to_string(), max_size);
+    let mut multipart =
+        Multipart::with_constraints(body, boundary, Constraints::new().size_limit(limits));
+    let mut form = StreamedForm {
+        fields: HashMap::new(),
+        file: None,
+    };
+    while let Some(mut field) = multipart.next_field().await.map_err(form_error)? {
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+        if name != file_field {
+            let text = field.text().await.map_err(form_error)?;
+            form.fields.insert(name, text);
+            continue;
+        }
+        let mut file = SpooledFile {
+            name: field.file_name().map(str::to_string),
+            path: std::env::temp_dir().join(Uuid::new_v4().to_string()),
+            size: 0,
+            sha_256: String::new(),
+            head: Vec::new(),
+        };
+        let mut spool = tokio::fs::File::create(&file.path).await?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = field.chunk().await.map_err(form_error)? {
+            hasher.update(&chunk);
+            let take = (SNIFF_LEN as usize - file.head.len()).min(chunk.len());
+            file.head.extend_from_slice(&chunk[..take]);
+            file.size += chunk.len() as i64;
+            spool.write_all(&chunk).await?;
+        }
+        spool.flush().await?;
+        file.sha_256 = format!("{:x}", hasher.finalize());
+        form.file = Some(file);
+    }
+    Ok(form)
+}
+
+fn form_error(e: multer::Error) -> AppError {
+    match e {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            AppError::FileTooLarge
+        }
+        _ => AppError::PayloadInvalid,
+    }
+}
@@ -1,18 +1,21 @@
-use futures::StreamExt;
-use reqwest;
+use crate::module::error::AppError;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_sdk_s3::primitives::ByteStream;
+use base64::prelude::{Engine, BASE64_STANDARD};
 use sha2::{Digest, Sha256};
 
-pub async fn get_size_and_hash(
-    client: &reqwest::Client,
-    url: &str,
-) -> Result<(i64, String), reqwest::Error> {
-    let response = client.get(url).send().await?;
-    let mut stream = response.bytes_stream();
+const CHALLENGE_RANGE_COUNT: usize = 4;
+const CHALLENGE_RANGE_LEN: i64 = 1024;
+
+pub async fn get_size_and_hash(mut stream: ByteStream) -> Result<(i64, String), AppError> {
     let mut hasher = Sha256::new();
     let mut total_size = 0;
 
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|e| AppError::MinioClientError(e.to_string()))?
+    {
         total_size += chunk.len() as i64;
         hasher.update(&chunk);
     }
@@ -20,3 +23,44 @@ pub async fn get_size_and_hash(
     let hash = format!("{:x}", hasher.finalize());
     Ok((total_size, hash))
 }
+
+// S3 reports checksums base64 encoded, file.sha_256 keeps hex
+pub fn base64_to_hex(checksum: &str) -> Option<String> {
+    let bytes = BASE64_STANDARD.decode(checksum).ok()?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn hex_to_base64(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(BASE64_STANDARD.encode(bytes))
+}
+
+// S3 carries CRC32C as the base64 of its four big-endian bytes
+pub fn crc32c_to_base64(crc: u32) -> String {
+    BASE64_STANDARD.encode(crc.to_be_bytes())
+}
+
+// Pick random inclusive byte ranges the client must hash to prove it holds the file
+pub fn challenge_ranges(size: i64) -> Vec<(i64, i64)> {
+    if size <= 0 {
+        return Vec::new();
+    }
+    let len = size.min(CHALLENGE_RANGE_LEN);
+    (0..CHALLENGE_RANGE_COUNT)
+        .map(|_| {
+            let start = (OsRng.next_u64() % (size - len + 1) as u64) as i64;
+            (start, start + len - 1)
+        })
+        .collect()
+}
+
+pub fn hash_chunks(chunks: &[Vec<u8>]) -> String {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    format!("{:x}", hasher.finalize())
+}
@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    // Inclusive start and end
+    Satisfiable(i64, i64),
+    Unsatisfiable,
+    // Absent, malformed or multi-range headers fall back to the whole body
+    Full,
+}
+
+pub fn parse_range(header: Option<&str>, size: i64) -> RangeRequest {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = match (start.parse::<i64>(), end.parse::<i64>()) {
+        // bytes=-500, the last 500 bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix <= 0 || size == 0 {
+                return RangeRequest::Unsatisfiable;
+            }
+            ((size - suffix).max(0), size - 1)
+        }
+        (Ok(start), Err(_)) if end.is_empty() => (start, size - 1),
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(size - 1)),
+        _ => return RangeRequest::Full,
+    };
+    if start < 0 || start >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Satisfiable(start, end)
+}
+
+// If-None-Match matches on the opaque tag, weak or not
+pub fn etag_matches(header: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn not_modified_since(header: &str, modified: &DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc2822(header.trim())
+        .map(|since| modified.timestamp() <= since.timestamp())
+        .unwrap_or(false)
+}
+
+// ASCII fallback for old clients plus the RFC 5987 encoded original name
+pub fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_forms() {
+        assert_eq!(parse_range(None, 100), RangeRequest::Full);
+        assert_eq!(
+            parse_range(Some("bytes=0-9"), 100),
+            RangeRequest::Satisfiable(0, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-"), 100),
+            RangeRequest::Satisfiable(90, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-10"), 100),
+            RangeRequest::Satisfiable(90, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=50-500"), 100),
+            RangeRequest::Satisfiable(50, 99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn disposition_encodes_non_ascii() {
+        assert_eq!(
+            content_disposition("attachment", "报告 1.pdf"),
+            "attachment; filename=\"__ 1.pdf\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A%201.pdf"
+        );
+    }
+}
@@ -25,6 +25,9 @@ pub struct Claims {
 pub enum Operation {
     ItemCreate = 1,
     FromFileStartUpload = 2,
+    HashChallenge = 3,
+    StoragePart = 4,
+    StorageGet = 5,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,495 @@
+use crate::module::error::AppError;
+use crate::util::hash::{crc32c_to_base64, hex_to_base64};
+use crate::util::jwt::{create_payload, validate_payload, Operation};
+use crate::util::storage::{
+    ChecksumAlgorithm, ObjectHead, PartChecksum, StorageBackend, StoredPart, UploadOwner,
+    UploadedPart,
+};
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use crc_fast::{CrcAlgorithm, Digest as CrcDigest};
+use salvo::async_trait;
+use sha2::{Digest, Sha256};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+const MULTIPART_DIR: &str = ".multipart";
+
+// Objects live under {root}/{bucket}/{key}, unfinished parts under {root}/.multipart/{upload_id}
+pub struct LocalStorage {
+    root: PathBuf,
+    url: String,
+}
+
+// What a proxied part upload URL grants, only while its session is still uploading
+pub struct LocalPartTarget {
+    pub owner: UploadOwner,
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+    pub part: i64,
+    pub checksum: Option<PartChecksum>,
+}
+
+// What a proxied download URL grants
+pub struct LocalObjectTarget {
+    pub bucket: String,
+    pub key: String,
+    pub content_type: String,
+    pub file_name: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, url: &str) -> Self {
+        LocalStorage {
+            root: PathBuf::from(root),
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn object_path(&self, bucket: &str, key: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(bucket).join(key);
+        let plain = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        if bucket.is_empty() || key.is_empty() || bucket.starts_with('.') || !plain {
+            return Err(AppError::StorageError(format!("Bad object key {}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, AppError> {
+        let upload_id = Uuid::parse_str(upload_id)
+            .map_err(|_e| AppError::StorageError(format!("Bad upload id {}", upload_id)))?;
+        Ok(self.root.join(MULTIPART_DIR).join(upload_id.to_string()))
+    }
+
+    // Part files are named {part}-{etag}-{crc32c}, the etag being the hex SHA-256 of the part
+    async fn read_parts(&self, upload_id: &str) -> Result<Vec<(StoredPart, PathBuf)>, AppError> {
+        let mut dir = fs::read_dir(self.upload_dir(upload_id)?).await?;
+        let mut parts = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let mut fields = name.splitn(3, '-');
+            let (Some(part), Some(etag)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let Ok(part) = part.parse::<i64>() else {
+                continue;
+            };
+            let crc = fields
+                .next()
+                .and_then(|crc| u32::from_str_radix(crc, 16).ok());
+            parts.push((
+                StoredPart {
+                    part,
+                    etag: Some(etag.to_string()),
+                    size: Some(entry.metadata().await?.len() as i64),
+                    checksum_sha256: hex_to_base64(etag),
+                    checksum_crc32c: crc.map(crc32c_to_base64),
+                },
+                entry.path(),
+            ));
+        }
+        parts.sort_by_key(|(part, _)| part.part);
+        Ok(parts)
+    }
+}
+
+// Write through a temp file so readers never see half an object, returns hex SHA-256 and CRC32C
+async fn write_stream(path: &Path, mut body: ByteStream) -> Result<(String, u32), AppError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let temp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+    let mut file = fs::File::create(&temp).await?;
+    let mut hasher = Sha256::new();
+    let mut crc = CrcDigest::new(CrcAlgorithm::Crc32Iscsi);
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .map_err(|e| AppError::StorageError(e.to_string()))?
+    {
+        hasher.update(&chunk);
+        crc.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    fs::rename(&temp, path).await?;
+    Ok((format!("{:x}", hasher.finalize()), crc.finalize() as u32))
+}
+
+fn checksum_matches(stored: &StoredPart, checksum: Option<&PartChecksum>) -> bool {
+    checksum.is_none_or(|c| stored.checksum(c.algorithm) == Some(c.value.as_str()))
+}
+
+pub fn validate_part_token(token: &str) -> Result<LocalPartTarget, AppError> {
+    let payload = validate_payload(token)?;
+    if payload.operation != Operation::StoragePart {
+        return Err(AppError::PayloadInvalid);
+    }
+    let data = payload.data.ok_or(AppError::PayloadInvalid)?;
+    let mut fields = data.splitn(5, ':');
+    let (Some(session_id), Some(bucket), Some(part), Some(checksum), Some(key)) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return Err(AppError::PayloadInvalid);
+    };
+    let checksum = match checksum.split_once('=') {
+        Some((algorithm, value)) => Some(PartChecksum {
+            algorithm: ChecksumAlgorithm::parse(algorithm).ok_or(AppError::PayloadInvalid)?,
+            value: value.to_string(),
+        }),
+        None => None,
+    };
+    Ok(LocalPartTarget {
+        owner: UploadOwner {
+            user_id: payload.uid,
+            session_id: Uuid::parse_str(session_id).map_err(|_e| AppError::PayloadInvalid)?,
+        },
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        upload_id: payload.id,
+        part: part.parse().map_err(|_e| AppError::PayloadInvalid)?,
+        checksum,
+    })
+}
+
+pub fn validate_object_token(token: &str) -> Result<LocalObjectTarget, AppError> {
+    let payload = validate_payload(token)?;
+    if payload.operation != Operation::StorageGet {
+        return Err(AppError::PayloadInvalid);
+    }
+    let data = payload.data.ok_or(AppError::PayloadInvalid)?;
+    let mut fields = data.splitn(3, ':');
+    let (Some(bucket), Some(content_type), Some(file_name)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(AppError::PayloadInvalid);
+    };
+    Ok(LocalObjectTarget {
+        bucket: bucket.to_string(),
+        key: payload.id,
+        content_type: content_type.to_string(),
+        file_name: file_name.to_string(),
+    })
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    // Both checksums are kept for every part, the algorithm needs no setup here
+    async fn create_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        _algorithm: Option<ChecksumAlgorithm>,
+    ) -> Result<String, AppError> {
+        self.object_path(bucket, key)?;
+        let upload_id = Uuid::new_v4().to_string();
+        fs::create_dir_all(self.upload_dir(&upload_id)?).await?;
+        Ok(upload_id)
+    }
+
+    async fn part_upload_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part: i64,
+        checksum: Option<&PartChecksum>,
+        owner: &UploadOwner,
+    ) -> Result<String, AppError> {
+        let checksum = checksum
+            .map(|c| format!("{}={}", c.algorithm.as_str(), c.value))
+            .unwrap_or_default();
+        let token = create_payload(
+            owner.user_id,
+            upload_id.to_string(),
+            Some(format!(
+                "{}:{}:{}:{}:{}",
+                owner.session_id, bucket, part, checksum, key
+            )),
+            Operation::StoragePart,
+        )?;
+        Ok(format!("{}/part?token={}", self.url, token))
+    }
+
+    async fn upload_part(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        upload_id: &str,
+        part: i64,
+        body: ByteStream,
+        checksum: Option<&PartChecksum>,
+    ) -> Result<String, AppError> {
+        let dir = self.upload_dir(upload_id)?;
+        if !fs::try_exists(&dir).await? {
+            return Err(AppError::UploadSessionNotExists);
+        }
+        let staging = dir.join(format!(".{}", Uuid::new_v4()));
+        let (etag, crc) = write_stream(&staging, body).await?;
+        let stored = StoredPart {
+            part,
+            etag: Some(etag.clone()),
+            size: None,
+            checksum_sha256: hex_to_base64(&etag),
+            checksum_crc32c: Some(crc32c_to_base64(crc)),
+        };
+        if !checksum_matches(&stored, checksum) {
+            fs::remove_file(&staging).await?;
+            return Err(AppError::ChecksumMismatch);
+        }
+        // A re-sent part replaces the earlier one
+        for (stored, path) in self.read_parts(upload_id).await? {
+            if stored.part == part {
+                fs::remove_file(path).await?;
+            }
+        }
+        fs::rename(&staging, dir.join(format!("{}-{}-{:08x}", part, etag, crc))).await?;
+        Ok(etag)
+    }
+
+    async fn list_parts(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<StoredPart>, AppError> {
+        Ok(self
+            .read_parts(upload_id)
+            .await?
+            .into_iter()
+            .map(|(part, _)| part)
+            .collect())
+    }
+
+    async fn complete_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<(), AppError> {
+        let path = self.object_path(bucket, key)?;
+        let stored = self.read_parts(upload_id).await?;
+        if parts.is_empty() {
+            return Err(AppError::StorageError("No parts to complete".to_string()));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let temp = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+        let mut file = fs::File::create(&temp).await?;
+        for part in parts {
+            let etag = part.etag.trim_matches('"');
+            let Some((stored_part, part_path)) = stored
+                .iter()
+                .find(|(p, _)| p.part == part.part && p.etag.as_deref() == Some(etag))
+            else {
+                drop(file);
+                let _ = fs::remove_file(&temp).await;
+                return Err(AppError::StorageError(format!(
+                    "Part {} not uploaded",
+                    part.part
+                )));
+            };
+            if !checksum_matches(stored_part, part.checksum.as_ref()) {
+                drop(file);
+                let _ = fs::remove_file(&temp).await;
+                return Err(AppError::ChecksumMismatch);
+            }
+            let mut part_file = fs::File::open(part_path).await?;
+            tokio::io::copy(&mut part_file, &mut file).await?;
+        }
+        file.flush().await?;
+        fs::rename(&temp, &path).await?;
+        fs::remove_dir_all(self.upload_dir(upload_id)?).await?;
+        Ok(())
+    }
+
+    async fn abort_multipart(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<(), AppError> {
+        fs::remove_dir_all(self.upload_dir(upload_id)?).await?;
+        Ok(())
+    }
+
+    async fn download_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_name: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, AppError> {
+        self.object_path(bucket, key)?;
+        let token = create_payload(
+            Uuid::nil(),
+            key.to_string(),
+            Some(format!(
+                "{}:{}:{}",
+                bucket,
+                content_type.unwrap_or("application/octet-stream"),
+                file_name
+            )),
+            Operation::StorageGet,
+        )?;
+        Ok(format!("{}/object?token={}", self.url, token))
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<ByteStream, AppError> {
+        ByteStream::from_path(self.object_path(bucket, key)?)
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+
+    async fn get_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<ByteStream, AppError> {
+        ByteStream::read_from()
+            .path(self.object_path(bucket, key)?)
+            .offset(start.max(0) as u64)
+            .length(Length::Exact((end - start + 1).max(0) as u64))
+            .build()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+
+    async fn get_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, AppError> {
+        let mut file = fs::File::open(self.object_path(bucket, key)?).await?;
+        file.seek(SeekFrom::Start(start.max(0) as u64)).await?;
+        let mut data = Vec::new();
+        file.take((end - start + 1).max(0) as u64)
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
+    }
+
+    async fn put(&self, bucket: &str, key: &str, body: ByteStream) -> Result<(), AppError> {
+        write_stream(&self.object_path(bucket, key)?, body).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.object_path(bucket, key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectHead, AppError> {
+        let metadata = fs::metadata(self.object_path(bucket, key)?).await?;
+        Ok(ObjectHead {
+            size: metadata.len() as i64,
+            sha_256: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn multipart_round_trip() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let storage = LocalStorage::new(root.to_str().unwrap(), "http://localhost");
+        let upload_id = storage
+            .create_multipart("file", "key", Some(ChecksumAlgorithm::Crc32c))
+            .await
+            .unwrap();
+        // CRC32C of "world"
+        let checksum = PartChecksum {
+            algorithm: ChecksumAlgorithm::Crc32c,
+            value: crc32c_to_base64(0x31aa_814e),
+        };
+        let second = storage
+            .upload_part(
+                "file",
+                "key",
+                &upload_id,
+                2,
+                ByteStream::from_static(b"world"),
+                Some(&checksum),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .upload_part(
+                    "file",
+                    "key",
+                    &upload_id,
+                    1,
+                    ByteStream::from_static(b"hello "),
+                    Some(&checksum),
+                )
+                .await,
+            Err(AppError::ChecksumMismatch)
+        ));
+        let first = storage
+            .upload_part(
+                "file",
+                "key",
+                &upload_id,
+                1,
+                ByteStream::from_static(b"hello "),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .list_parts("file", "key", &upload_id)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        let parts = vec![
+            UploadedPart {
+                part: 1,
+                etag: first,
+                checksum: None,
+            },
+            UploadedPart {
+                part: 2,
+                etag: second,
+                checksum: Some(checksum),
+            },
+        ];
+        storage
+            .complete_multipart("file", "key", &upload_id, parts)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.head("file", "key").await.unwrap().size, 11);
+        assert_eq!(
+            storage.get_range("file", "key", 6, 10).await.unwrap(),
+            b"world"
+        );
+        assert!(storage.object_path("file", "../key").is_err());
+        storage.delete("file", "key").await.unwrap();
+        assert!(storage.head("file", "key").await.is_err());
+        let _ = fs::remove_dir_all(root).await;
+    }
+}
@@ -0,0 +1,40 @@
+pub const SNIFF_LEN: i64 = 8192;
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+// Magic numbers first, then plain UTF-8 text, anything else is opaque binary
+pub fn sniff_mime(head: &[u8]) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+    if head.is_empty() {
+        return OCTET_STREAM.to_string();
+    }
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sniffed window may cut a multi-byte character at its end
+        Err(e) => e.error_len().is_none(),
+    };
+    if text && !head.contains(&0) {
+        "text/plain".to_string()
+    } else {
+        OCTET_STREAM.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_magic_and_text() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_mime(b"\xff\xd8\xff\xe0\0\x10JFIF\0"), "image/jpeg");
+        assert_eq!(sniff_mime(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff_mime("纯文本 notes".as_bytes()), "text/plain");
+        // A multi-byte character cut by the sniff window is still text
+        assert_eq!(sniff_mime(&"报告".as_bytes()[..4]), "text/plain");
+        assert_eq!(sniff_mime(b""), OCTET_STREAM);
+        assert_eq!(sniff_mime(b"\x01\x02\0\xfe\xff"), OCTET_STREAM);
+    }
+}
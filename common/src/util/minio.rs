@@ -1,11 +1,22 @@
 use crate::config;
 use crate::config::Config;
 use crate::module::error::AppError;
+use crate::util::hash::base64_to_hex;
+use crate::util::http::content_disposition;
+use crate::util::storage::{
+    ChecksumAlgorithm, ObjectHead, PartChecksum, StorageBackend, StoredPart, UploadOwner,
+    UploadedPart,
+};
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::config::{Credentials, Region};
 use aws_sdk_s3::presigning::PresigningConfig;
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm as S3ChecksumAlgorithm, ChecksumMode, ChecksumType, CompletedMultipartUpload,
+    CompletedPart,
+};
 use aws_sdk_s3::Client;
+use salvo::async_trait;
 use std::time::Duration;
 
 pub async fn generate_client(config: &Config) -> Client {
@@ -25,79 +36,312 @@ pub async fn generate_client(config: &Config) -> Client {
     Client::new(&config)
 }
 
-pub async fn generate_upload_id(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-) -> Result<String, AppError> {
-    let response = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    response.upload_id.ok_or(AppError::MinioClientError(
-        "Cannot get upload id".to_string(),
-    ))
+pub struct S3Storage {
+    client: Client,
 }
 
-pub async fn generate_part_upload_url(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-    part_number: i64,
-    upload_id: String,
-) -> Result<String, AppError> {
-    let presigned_request = client
-        .upload_part()
-        .bucket(bucket)
-        .key(key)
-        .upload_id(upload_id)
-        .part_number(part_number as i32)
-        .presigned(
-            PresigningConfig::expires_in(Duration::from_secs(config!().upload.part_exp_min * 60))
-                .map_err(|e| AppError::InnerError(e.to_string()))?,
-        )
-        .await?;
-    Ok(presigned_request.uri().to_string())
+impl S3Storage {
+    pub fn new(client: Client) -> Self {
+        S3Storage { client }
+    }
 }
 
-pub async fn generate_download_url(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-    file_name: &str,
-) -> Result<String, AppError> {
-    let presigned = client
-        .get_object()
-        .bucket(bucket)
-        .key(key)
-        .response_content_disposition(format!("attachment; filename=\"{}\"", file_name))
-        .presigned(
-            PresigningConfig::expires_in(Duration::from_secs(14400))
-                .map_err(|e| AppError::InnerError(e.to_string()))?,
-        )
-        .await?;
-    Ok(presigned.uri().to_string())
+fn s3_algorithm(algorithm: ChecksumAlgorithm) -> S3ChecksumAlgorithm {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => S3ChecksumAlgorithm::Sha256,
+        ChecksumAlgorithm::Crc32c => S3ChecksumAlgorithm::Crc32C,
+    }
 }
 
-pub async fn complete_upload(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-    upload_id: &String,
-    parts: Vec<CompletedPart>,
-) -> Result<(), AppError> {
-    let completed = CompletedMultipartUpload::builder()
-        .set_parts(Some(parts))
-        .build();
-    client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .upload_id(upload_id)
-        .multipart_upload(completed)
-        .send()
-        .await?;
-    Ok(())
+fn checksum_value(checksum: Option<&PartChecksum>, algorithm: ChecksumAlgorithm) -> Option<String> {
+    checksum
+        .filter(|c| c.algorithm == algorithm)
+        .map(|c| c.value.clone())
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn create_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        algorithm: Option<ChecksumAlgorithm>,
+    ) -> Result<String, AppError> {
+        let response = self
+            .client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .set_checksum_algorithm(algorithm.map(s3_algorithm))
+            .send()
+            .await?;
+        response.upload_id.ok_or(AppError::MinioClientError(
+            "Cannot get upload id".to_string(),
+        ))
+    }
+
+    async fn part_upload_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part: i64,
+        checksum: Option<&PartChecksum>,
+        _owner: &UploadOwner,
+    ) -> Result<String, AppError> {
+        let presigned_request = self
+            .client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part as i32)
+            .set_checksum_sha256(checksum_value(checksum, ChecksumAlgorithm::Sha256))
+            .set_checksum_crc32_c(checksum_value(checksum, ChecksumAlgorithm::Crc32c))
+            .presigned(
+                PresigningConfig::expires_in(Duration::from_secs(
+                    config!().upload.part_exp_min * 60,
+                ))
+                .map_err(|e| AppError::InnerError(e.to_string()))?,
+            )
+            .await?;
+        Ok(presigned_request.uri().to_string())
+    }
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part: i64,
+        body: ByteStream,
+        checksum: Option<&PartChecksum>,
+    ) -> Result<String, AppError> {
+        let response = self
+            .client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part as i32)
+            .set_checksum_sha256(checksum_value(checksum, ChecksumAlgorithm::Sha256))
+            .set_checksum_crc32_c(checksum_value(checksum, ChecksumAlgorithm::Crc32c))
+            .body(body)
+            .send()
+            .await?;
+        response
+            .e_tag
+            .ok_or(AppError::MinioClientError("Cannot get etag".to_string()))
+    }
+
+    async fn list_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<StoredPart>, AppError> {
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let response = self
+                .client
+                .list_parts()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .set_part_number_marker(marker)
+                .send()
+                .await?;
+            parts.extend(
+                response
+                    .parts
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|part| StoredPart {
+                        part: part.part_number.unwrap_or_default() as i64,
+                        etag: part.e_tag,
+                        size: part.size,
+                        checksum_sha256: part.checksum_sha256,
+                        checksum_crc32c: part.checksum_crc32_c,
+                    }),
+            );
+            match (response.is_truncated, response.next_part_number_marker) {
+                (Some(true), Some(next)) => marker = Some(next),
+                _ => break,
+            }
+        }
+        Ok(parts)
+    }
+
+    async fn complete_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<(), AppError> {
+        let completed = CompletedMultipartUpload::builder()
+            .set_parts(Some(
+                parts
+                    .into_iter()
+                    .map(|part| {
+                        let checksum = part.checksum.as_ref();
+                        CompletedPart::builder()
+                            .part_number(part.part as i32)
+                            .e_tag(part.etag.clone())
+                            .set_checksum_sha256(checksum_value(
+                                checksum,
+                                ChecksumAlgorithm::Sha256,
+                            ))
+                            .set_checksum_crc32_c(checksum_value(
+                                checksum,
+                                ChecksumAlgorithm::Crc32c,
+                            ))
+                            .build()
+                    })
+                    .collect(),
+            ))
+            .build();
+        self.client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(completed)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn abort_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), AppError> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn download_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_name: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, AppError> {
+        let presigned = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .response_content_disposition(content_disposition("attachment", file_name))
+            .set_response_content_type(content_type.map(str::to_string))
+            .presigned(
+                PresigningConfig::expires_in(Duration::from_secs(14400))
+                    .map_err(|e| AppError::InnerError(e.to_string()))?,
+            )
+            .await?;
+        Ok(presigned.uri().to_string())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<ByteStream, AppError> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(response.body)
+    }
+
+    async fn get_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<ByteStream, AppError> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", start, end))
+            .send()
+            .await?;
+        Ok(response.body)
+    }
+
+    async fn get_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, AppError> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", start, end))
+            .send()
+            .await?;
+        let data = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| AppError::MinioClientError(e.to_string()))?;
+        Ok(data.into_bytes().to_vec())
+    }
+
+    async fn put(&self, bucket: &str, key: &str, body: ByteStream) -> Result<(), AppError> {
+        self.client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(body)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<(), AppError> {
+        self.client
+            .delete_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectHead, AppError> {
+        let response = self
+            .client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await?;
+        // Multipart objects carry a checksum of checksums, useless as the file hash
+        let sha_256 = response
+            .checksum_sha256
+            .filter(|_| response.checksum_type != Some(ChecksumType::Composite))
+            .filter(|c| !c.contains('-'))
+            .and_then(|c| base64_to_hex(&c));
+        Ok(ObjectHead {
+            size: response.content_length.unwrap_or_default(),
+            sha_256,
+        })
+    }
 }
@@ -1,8 +1,16 @@
 pub mod router;
 pub mod database;
 pub mod minio;
+pub mod storage;
+pub mod local_storage;
 pub mod jwt;
 pub mod result;
 pub mod path;
 pub mod hash;
-pub mod nacos;
\ No newline at end of file
+pub mod http;
+pub mod mime;
+pub mod thumbnail;
+pub mod multipart;
+pub mod nacos;
+pub mod cursor;
+pub mod form;
\ No newline at end of file
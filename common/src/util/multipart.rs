@@ -0,0 +1,53 @@
+// S3 multipart limits, only the last part may be smaller than MIN_PART_SIZE
+pub const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+pub const MAX_PART_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+pub const MAX_PARTS: i64 = 10_000;
+
+// Honour the preferred part size where S3 allows it, grow parts until the file fits MAX_PARTS
+pub fn plan_part_size(size: i64, preferred: i64) -> Option<i64> {
+    if size < 0 {
+        return None;
+    }
+    let fitted = (size as u64).div_ceil(MAX_PARTS as u64) as i64;
+    let part_size = preferred.max(fitted).clamp(MIN_PART_SIZE, MAX_PART_SIZE);
+    (part_count(size, part_size) <= MAX_PARTS).then_some(part_size)
+}
+
+// An empty file still needs one (empty) part to complete
+pub fn part_count(size: i64, part_size: i64) -> i64 {
+    (size.max(1) as u64).div_ceil(part_size.max(1) as u64) as i64
+}
+
+pub fn expected_part_size(size: i64, part_size: i64, part: i64) -> i64 {
+    let count = part_count(size, part_size);
+    if part < count {
+        part_size
+    } else {
+        size - (count - 1) * part_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_limits() {
+        assert_eq!(plan_part_size(0, 8 << 20), Some(8 << 20));
+        assert_eq!(plan_part_size(100, 1), Some(MIN_PART_SIZE));
+        assert_eq!(plan_part_size(100, i64::MAX), Some(MAX_PART_SIZE));
+        // 100 GiB at 5 MiB would take 20480 parts
+        let size = 100 << 30;
+        let part_size = plan_part_size(size, MIN_PART_SIZE).unwrap();
+        assert!(part_count(size, part_size) <= MAX_PARTS);
+        assert_eq!(plan_part_size(MAX_PART_SIZE * MAX_PARTS + 1, 0), None);
+
+        assert_eq!(part_count(0, MIN_PART_SIZE), 1);
+        assert_eq!(expected_part_size(0, MIN_PART_SIZE, 1), 0);
+        assert_eq!(
+            expected_part_size(MIN_PART_SIZE + 3, MIN_PART_SIZE, 1),
+            MIN_PART_SIZE
+        );
+        assert_eq!(expected_part_size(MIN_PART_SIZE + 3, MIN_PART_SIZE, 2), 3);
+    }
+}
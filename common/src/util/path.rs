@@ -31,3 +31,40 @@ impl FilePathInfo {
         })
     }
 }
+
+// "/a/b/c" -> ["a", "b", "c"], empty and "." segments drop out, ".." is refused
+pub fn path_segments(path: &str) -> Result<Vec<String>, AppError> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .map(|segment| match segment {
+            ".." => Err(AppError::PathOrNameError),
+            _ => Ok(segment.to_string()),
+        })
+        .collect()
+}
+
+// "name.ext" -> "name (n).ext", dotfiles and extensionless names get the suffix at the end
+pub fn numbered_name(name: &str, n: u32) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{} ({}){}", &name[..dot], n, &name[dot..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments() {
+        assert_eq!(path_segments("/").unwrap(), Vec::<String>::new());
+        assert_eq!(
+            path_segments("/a//b/./c.txt").unwrap(),
+            vec!["a", "b", "c.txt"]
+        );
+        assert!(path_segments("/a/../b").is_err());
+        let info = FilePathInfo::from_str("/docs/2024/report.pdf").unwrap();
+        assert_eq!(info.file_name, "report.pdf");
+        assert_eq!(info.parent, "/docs/2024");
+    }
+}
@@ -20,6 +20,7 @@ pub enum ResultCode {
     HashError = 5003,
     PayloadCreateError = 5004,
     MinioClientError = 5005,
+    StorageError = 5006,
 
     MissingField = 4000,
 
@@ -33,12 +34,21 @@ pub enum ResultCode {
     CannotDeleteFolder = 4017,
     ShareCodeMismatched = 4018,
     UserOutSize = 4019,
+    HashChallengeMismatch = 4020,
+    FileTooLarge = 4021,
+    UploadPlanMismatch = 4022,
+    ChecksumMismatch = 4023,
+    InvalidParent = 4024,
+    InvalidCursor = 4025,
+    InvalidValue = 4026,
 
     UserExists = 4090,
+    ItemNameConflict = 4091,
     UserNotExists = 4040,
     FileNotExists = 4041,
     ItemNotExists = 4042,
     ShareFileNotFound = 4043,
+    UploadSessionNotExists = 4044,
 }
 
 impl<T: Serialize> ResultData<T> {
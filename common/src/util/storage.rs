@@ -0,0 +1,200 @@
+use crate::config::{Config, StorageKind};
+use crate::module::error::AppError;
+use crate::util::local_storage::LocalStorage;
+use crate::util::minio::{generate_client, S3Storage};
+use aws_sdk_s3::primitives::{ByteStream, ByteStreamError};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::Stream;
+use salvo::async_trait;
+use salvo::http::body::BytesFrame;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Crc32c,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Crc32c => "crc32c",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(ChecksumAlgorithm::Sha256),
+            "crc32c" => Some(ChecksumAlgorithm::Crc32c),
+            _ => None,
+        }
+    }
+
+    // Checksums travel base64 encoded, as in the x-amz-checksum-* headers
+    pub fn is_valid(&self, value: &str) -> bool {
+        let len = BASE64_STANDARD.decode(value).map(|bytes| bytes.len()).ok();
+        match self {
+            ChecksumAlgorithm::Sha256 => len == Some(32),
+            ChecksumAlgorithm::Crc32c => len == Some(4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredPart {
+    pub part: i64,
+    pub etag: Option<String>,
+    pub size: Option<i64>,
+    pub checksum_sha256: Option<String>,
+    pub checksum_crc32c: Option<String>,
+}
+
+impl StoredPart {
+    pub fn checksum(&self, algorithm: ChecksumAlgorithm) -> Option<&str> {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => self.checksum_sha256.as_deref(),
+            ChecksumAlgorithm::Crc32c => self.checksum_crc32c.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadedPart {
+    pub part: i64,
+    pub etag: String,
+    pub checksum: Option<PartChecksum>,
+}
+
+// Who a part url is issued to. Backends that sign their own urls tie them to it
+#[derive(Debug, Clone, Copy)]
+pub struct UploadOwner {
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectHead {
+    pub size: i64,
+    // Hex SHA-256, only when the backend holds one for the whole object
+    pub sha_256: Option<String>,
+}
+
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    // Parts of an upload created with an algorithm must each carry a checksum of it
+    async fn create_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        algorithm: Option<ChecksumAlgorithm>,
+    ) -> Result<String, AppError>;
+
+    // The checksum is signed into the url, the part is refused when its bytes differ
+    async fn part_upload_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part: i64,
+        checksum: Option<&PartChecksum>,
+        owner: &UploadOwner,
+    ) -> Result<String, AppError>;
+
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part: i64,
+        body: ByteStream,
+        checksum: Option<&PartChecksum>,
+    ) -> Result<String, AppError>;
+
+    async fn list_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<StoredPart>, AppError>;
+
+    // Parts in the order they make up the object
+    async fn complete_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<(), AppError>;
+
+    async fn abort_multipart(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), AppError>;
+
+    async fn download_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_name: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, AppError>;
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<ByteStream, AppError>;
+
+    // Inclusive byte range, streamed
+    async fn get_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<ByteStream, AppError>;
+
+    // Inclusive byte range
+    async fn get_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, AppError>;
+
+    async fn put(&self, bucket: &str, key: &str, body: ByteStream) -> Result<(), AppError>;
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<(), AppError>;
+
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectHead, AppError>;
+}
+
+pub async fn generate_storage(config: &Config) -> Box<dyn StorageBackend> {
+    match config.storage.backend {
+        StorageKind::Minio => Box::new(S3Storage::new(generate_client(config).await)),
+        StorageKind::Local => Box::new(LocalStorage::new(
+            &config.storage.local_root,
+            &config.storage.local_url,
+        )),
+    }
+}
+
+// Adapt an object body for Response::stream
+pub fn into_body_stream(
+    body: ByteStream,
+) -> impl Stream<Item = Result<BytesFrame, ByteStreamError>> + Send + 'static {
+    futures::stream::unfold(body, |mut body| async move {
+        body.next()
+            .await
+            .map(|chunk| (chunk.map(BytesFrame::data), body))
+    })
+}
@@ -0,0 +1,67 @@
+use crate::module::error::AppError;
+use image::codecs::jpeg::JpegEncoder;
+use image::ImageReader;
+use std::io::Cursor;
+
+const THUMBNAIL_QUALITY: u8 = 80;
+
+pub fn is_thumbnail_source(mime: &str) -> bool {
+    matches!(
+        mime,
+        "image/jpeg" | "image/png" | "image/webp" | "image/gif"
+    )
+}
+
+// One JPEG per size, fitted into a size x size box, GIF only keeps its first frame
+pub fn generate_thumbnails(data: &[u8], sizes: &[u32]) -> Result<Vec<(u32, Vec<u8>)>, AppError> {
+    let image = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    sizes
+        .iter()
+        .map(|&size| {
+            let fitted = if image.width() > size || image.height() > size {
+                image.thumbnail(size, size)
+            } else {
+                image.clone()
+            };
+            let mut out = Vec::new();
+            JpegEncoder::new_with_quality(&mut out, THUMBNAIL_QUALITY)
+                .encode_image(&fitted.into_rgb8())
+                .map_err(|e| AppError::InnerError(e.to_string()))?;
+            Ok((size, out))
+        })
+        .collect()
+}
+
+// Smallest configured size covering the request, else the largest one
+pub fn pick_thumbnail_size(sizes: &[u32], wanted: Option<u32>) -> Option<u32> {
+    let wanted = wanted.unwrap_or(0);
+    sizes
+        .iter()
+        .copied()
+        .filter(|&size| size >= wanted)
+        .min()
+        .or_else(|| sizes.iter().copied().max())
+}
+
+pub fn thumbnail_key(prefix: &str, size: u32) -> String {
+    format!("{}/{}", prefix, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_size() {
+        let sizes = [128, 512, 256];
+        assert_eq!(pick_thumbnail_size(&sizes, None), Some(128));
+        assert_eq!(pick_thumbnail_size(&sizes, Some(200)), Some(256));
+        assert_eq!(pick_thumbnail_size(&sizes, Some(256)), Some(256));
+        assert_eq!(pick_thumbnail_size(&sizes, Some(1024)), Some(512));
+        assert_eq!(pick_thumbnail_size(&[], Some(64)), None);
+        assert_eq!(thumbnail_key("ab-cd", 256), "ab-cd/256");
+    }
+}
@@ -0,0 +1,16 @@
+-- Multipart upload sessions, one row per start-upload. Safe to re-run.
+
+create table if not exists "upload_session" (
+    id          uuid primary key,
+    create_time timestamptz not null default now(),
+    update_time timestamptz not null default now(),
+    delete_flag smallint not null default 0,
+    user_id     uuid not null,
+    file_id     uuid not null,
+    upload_id   text not null,
+    path        text not null,
+    size        bigint not null,
+    status      smallint not null default 0
+);
+create index if not exists upload_session_status_idx on "upload_session" (status, update_time) where delete_flag = 0;
+create index if not exists upload_session_user_idx on "upload_session" (user_id) where delete_flag = 0;
@@ -0,0 +1,2 @@
+-- Part size fixed by the server-side upload plan, null for sessions started before it
+alter table "upload_session" add column if not exists part_size bigint;
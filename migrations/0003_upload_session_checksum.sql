@@ -0,0 +1,2 @@
+-- Per-part checksum algorithm chosen at start-upload, null when parts are not verified
+alter table "upload_session" add column if not exists checksum_algorithm text;
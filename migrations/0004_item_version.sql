@@ -0,0 +1,16 @@
+-- Earlier files of an item, and how many each user keeps. Safe to re-run.
+
+create table if not exists "item_version" (
+    id          uuid primary key,
+    create_time timestamptz not null default now(),
+    delete_flag smallint not null default 0,
+    user_id     uuid not null,
+    item_id     uuid not null,
+    file_id     uuid not null,
+    size        bigint not null default 0
+);
+create index if not exists item_version_item_idx on "item_version" (item_id, create_time) where delete_flag = 0;
+create index if not exists item_version_file_idx on "item_version" (file_id) where delete_flag = 0;
+
+-- Null uses the configured default
+alter table "user" add column if not exists version_retention integer;
@@ -0,0 +1,15 @@
+-- Recycle bin, one row per deleted root. Safe to re-run.
+
+create table if not exists "trash" (
+    id          uuid primary key,
+    create_time timestamptz not null default now(),
+    delete_flag smallint not null default 0,
+    user_id     uuid not null,
+    item_id     uuid not null,
+    size        bigint not null default 0
+);
+create index if not exists trash_user_idx on "trash" (user_id, create_time) where delete_flag = 0;
+
+-- Items deleted together share the trash row's id
+alter table "item" add column if not exists trash_id uuid;
+create index if not exists item_trash_idx on "item" (trash_id) where trash_id is not null;
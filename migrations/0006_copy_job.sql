@@ -0,0 +1,15 @@
+-- Background copies and how far they got. Safe to re-run.
+
+create table if not exists "copy_job" (
+    id          uuid primary key,
+    create_time timestamptz not null default now(),
+    update_time timestamptz not null default now(),
+    delete_flag smallint not null default 0,
+    user_id     uuid not null,
+    parent_id   uuid not null,
+    total       bigint not null default 0,
+    copied      bigint not null default 0,
+    status      smallint not null default 0,
+    message     text
+);
+create index if not exists copy_job_status_idx on "copy_job" (status, update_time) where delete_flag = 0;
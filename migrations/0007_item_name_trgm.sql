@@ -0,0 +1,11 @@
+-- Optional, only fuzzy name search uses it. Creating pg_trgm may need a superuser, without it
+-- the migration only warns and fuzzy search falls back to substring matching. Safe to re-run.
+
+do $$
+begin
+    create extension if not exists pg_trgm;
+    create index if not exists item_logic_name_trgm_idx on "item" using gin (logic_name gin_trgm_ops);
+exception when others then
+    raise warning 'pg_trgm unavailable, fuzzy search falls back to substring: %', sqlerrm;
+end
+$$;
@@ -0,0 +1,33 @@
+-- Folder stats are cached per user under tree_stamp, any write to a user's items bumps it.
+-- File size corrections bump it from the service. Safe to re-run.
+
+alter table "user" add column if not exists tree_stamp bigint not null default 0;
+
+create or replace function item_tree_stamp() returns trigger language plpgsql as $$
+begin
+    if tg_op = 'INSERT' then
+        update "user" set tree_stamp = tree_stamp + 1 where id in (select user_id from new_rows);
+    elsif tg_op = 'DELETE' then
+        update "user" set tree_stamp = tree_stamp + 1 where id in (select user_id from old_rows);
+    else
+        update "user" set tree_stamp = tree_stamp + 1
+        where id in (select user_id from old_rows union select user_id from new_rows);
+    end if;
+    return null;
+end
+$$;
+
+drop trigger if exists item_tree_stamp_insert on "item";
+create trigger item_tree_stamp_insert after insert on "item"
+    referencing new table as new_rows
+    for each statement execute function item_tree_stamp();
+
+drop trigger if exists item_tree_stamp_update on "item";
+create trigger item_tree_stamp_update after update on "item"
+    referencing old table as old_rows new table as new_rows
+    for each statement execute function item_tree_stamp();
+
+drop trigger if exists item_tree_stamp_delete on "item";
+create trigger item_tree_stamp_delete after delete on "item"
+    referencing old table as old_rows
+    for each statement execute function item_tree_stamp();
@@ -0,0 +1,20 @@
+-- One live item per name in a folder. Duplicates left from before get the start of their id
+-- appended, the oldest keeps its name. Safe to re-run.
+
+with ranked as (
+    select id, logic_name, row_number() over (
+        partition by user_id, coalesce(parent_id, '00000000-0000-0000-0000-000000000000'::uuid), logic_name
+        order by create_time, id
+    ) as n
+    from "item" where delete_flag = 0
+)
+update "item" i set logic_name = case
+        when r.logic_name ~ '^.+\.[^.]*$'
+            then regexp_replace(r.logic_name, '^(.+)(\.[^.]*)$', '\1 (' || left(r.id::text, 8) || ')\2')
+        else r.logic_name || ' (' || left(r.id::text, 8) || ')'
+    end
+from ranked r where i.id = r.id and r.n > 1;
+
+create unique index if not exists item_name_unique_idx on "item" (
+    user_id, coalesce(parent_id, '00000000-0000-0000-0000-000000000000'::uuid), logic_name
+) where delete_flag = 0;
@@ -2,6 +2,7 @@ use crate::service::commit_service::CommitService;
 use common::config;
 use common::module::commit::Commit;
 use common::module::error::AppError;
+use common::util::cursor::CursorPage;
 use common::util::jwt::Claims;
 use common::util::result::{ResultCode, ResultData};
 use rbatis::Page;
@@ -16,7 +17,10 @@ use uuid::Uuid;
 struct CommitsDto {
     share_id: Uuid,
     code: Option<String>,
-    page: u64,
+    // Page-number mode, ignored once a cursor is sent
+    page: Option<u64>,
+    // Keyset mode, "" for the first page then each reply's next_cursor
+    cursor: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
@@ -34,17 +38,32 @@ struct DeleteCommitDto {
 #[endpoint(
     status_codes(200),
     responses(
-        (status_code = 200, description = "get commits", body = ResultData<Vec<Commit>>),
+        (status_code = 200, description = "get commits, a CursorPage<Commit> when a cursor is sent", body = ResultData<Vec<Commit>>),
     )
 )]
 pub async fn get_commit(
     commits_dto: JsonBody<CommitsDto>,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
+    if let Some(cursor) = &commits_dto.cursor {
+        let commits = CommitService::get_commit_cursor(
+            &commits_dto.share_id,
+            commits_dto.code.clone(),
+            cursor,
+            config!().page.size,
+        )
+        .await?;
+        res.render(Json(ResultData::<CursorPage<Commit>>::new(
+            "Get success",
+            Some(commits),
+            ResultCode::Success,
+        )));
+        return Ok(StatusCode::OK);
+    }
     let commits = CommitService::get_commit_page(
         &commits_dto.share_id,
         commits_dto.code.clone(),
-        commits_dto.page,
+        commits_dto.page.unwrap_or(1),
         config!().page.size,
     )
     .await?;
@@ -24,7 +24,7 @@ async fn main() {
 
     let config = &CONTEXT.config;
     CONTEXT.init_database().await;
-    CONTEXT.init_minio().await;
+    CONTEXT.init_storage().await;
 
     let router = openapi(
         Router::new().push(all_router()),
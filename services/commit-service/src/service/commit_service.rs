@@ -2,6 +2,7 @@ use common::db_pool;
 use common::module::commit::Commit;
 use common::module::error::AppError;
 use common::module::share::Share;
+use common::util::cursor::CursorPage;
 use rbatis::{Page, PageRequest};
 use uuid::Uuid;
 
@@ -24,6 +25,16 @@ impl CommitService {
         Ok(page)
     }
 
+    pub async fn get_commit_cursor(
+        share_id: &Uuid,
+        pickup_code: Option<String>,
+        cursor: &str,
+        limit: u64,
+    ) -> Result<CursorPage<Commit>, AppError> {
+        Self::verify_code(share_id, pickup_code).await?;
+        Commit::select_after_by_shareid(db_pool!(), cursor, limit, share_id).await
+    }
+
     pub async fn create_commit(
         user_id: &Uuid,
         share_id: &Uuid,
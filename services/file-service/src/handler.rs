@@ -1,68 +1,241 @@
-use crate::service::file_service::FileService;
-use aws_sdk_s3::types::CompletedPart;
-use common::{config, db_pool};
+use crate::service::file_service::{FileService, UploadTarget};
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, Utc};
+use common::{config, db_pool, storage};
 use common::module::error::AppError;
-use common::module::item::Item;
-use common::util::jwt::{create_payload, validate_payload, Claims, Operation};
+use common::module::copy_job::CopyJob;
+use common::module::item::{
+    Breadcrumb, ConflictPolicy, FolderStats, Item, ItemOrder, ItemSearch, ItemSort, ItemVo,
+    SearchMode, SortOrder,
+};
+use common::module::item_version::ItemVersionVo;
+use common::module::trash::TrashVo;
+use common::module::upload_session::{UploadSession, UploadedPartVo};
+use common::util::cursor::CursorPage;
+use common::util::form::{read_streamed_form, StreamedForm};
+use common::util::http::{
+    content_disposition, etag_matches, http_date, not_modified_since, parse_range, RangeRequest,
+};
+use common::util::jwt::Claims;
+use common::util::local_storage::{validate_object_token, validate_part_token};
+use common::util::multipart::part_count;
 use common::util::result::{ResultCode, ResultData};
+use common::util::storage::{into_body_stream, ChecksumAlgorithm};
+use futures::StreamExt;
+use salvo::http::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+};
 use salvo::http::StatusCode;
 use salvo::oapi::extract::{JsonBody, QueryParam};
 use salvo::oapi::ToSchema;
 use salvo::prelude::*;
+use rbatis::Page;
 use salvo::Response;
 use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+use tracing::error;
 use uuid::Uuid;
 use common::module::user::User;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct HashUploadDto {
     hash: String,
+    // Size of the client's file, the challenge ranges are drawn from it
+    size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct HashVerifyDto {
+    challenge: String,
+    digest: String,
     parent_id: Option<Uuid>,
-    logic_name: String,
+    logic_name: Option<String>,
+    // A full path instead of parent_id and logic_name
+    path: Option<String>,
+    // Upload as the next version of this item instead of a new one
+    item_id: Option<Uuid>,
+    conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct ByteRange {
+    start: i64,
+    end: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+// Issued whether or not the server holds the hash, only a passed challenge tells
+struct HashChallengeVo {
+    challenge: String,
+    ranges: Vec<ByteRange>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct MakeDirDto {
     parent_id: Option<Uuid>,
     logic_name: String,
+    conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StartUploadDto {
+    size: i64,
+    part_size: Option<i64>,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    checksums: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct PartUrlVo {
+    part: i64,
+    url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StartUploadVo {
+    session_id: Uuid,
+    credentials: String,
+    part_size: i64,
+    part_count: i64,
+    parts: Vec<PartUrlVo>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct PartUploadDto {
     part: i64,
+    checksum: Option<String>,
+    credentials: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct PartUrlsDto {
+    start: i64,
+    count: i64,
+    checksums: Option<Vec<String>>,
     credentials: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct FinishUploadedDto {
-    logic_name: String,
+    logic_name: Option<String>,
     parent_id: Option<Uuid>,
+    // A full path instead of parent_id and logic_name
+    path: Option<String>,
+    // Upload as the next version of this item instead of a new one
+    item_id: Option<Uuid>,
+    conflict: Option<ConflictPolicy>,
     credentials: String,
     parts: Vec<Part>,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct AbortUploadDto {
+    session_id: Uuid,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 struct Part {
     part: i64,
     etag: String,
+    checksum: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 struct ItemsDto {
+    // Page-number mode, ignored once a cursor is sent
+    page: Option<u64>,
+    // At most config.page.size, which is also the default
+    page_size: Option<u64>,
+    // Keyset mode, "" for the first page then each reply's next_cursor
+    cursor: Option<String>,
+    parent_id: Option<Uuid>,
+    // List the folder at this path instead of parent_id
+    path: Option<String>,
+    sort: Option<ItemSort>,
+    order: Option<SortOrder>,
+    // Defaults to true, always on when sorting by size
+    folders_first: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct SearchDto {
     page: u64,
+    page_size: Option<u64>,
+    query: Option<String>,
+    mode: Option<SearchMode>,
+    is_folder: Option<bool>,
+    file_type: Option<String>,
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    root_id: Option<Uuid>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct ItemPageVo {
+    page_no: u64,
+    page_size: u64,
+    total: u64,
+    records: Vec<ItemVo>,
+}
+
+impl From<Page<ItemVo>> for ItemPageVo {
+    fn from(page: Page<ItemVo>) -> Self {
+        ItemPageVo {
+            page_no: page.page_no,
+            page_size: page.page_size,
+            total: page.total,
+            records: page.records,
+        }
+    }
+}
+
+fn page_size(requested: Option<u64>) -> u64 {
+    let max = config!().page.size;
+    requested.unwrap_or(max).clamp(1, max)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct PathDto {
+    path: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct ArchiveDto {
+    item_ids: Vec<Uuid>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct RestoreVersionDto {
+    item_id: Uuid,
+    version_id: Uuid,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct CopyItemsDto {
+    item_ids: Vec<Uuid>,
     parent_id: Option<Uuid>,
+    conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+struct TrashDto {
+    trash_id: Uuid,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 struct MoveItemDto {
     item_id: Uuid,
     parent_id: Option<Uuid>,
+    conflict: Option<ConflictPolicy>,
 }
 
 #[endpoint(
     status_codes(200),
     responses(
-        (status_code = 200, description = "Check file in server", body = ResultData<bool>),
+        (status_code = 200, description = "Check file in server", body = ResultData<HashChallengeVo>),
     )
 )]
 pub async fn upload_by_hash(
@@ -73,44 +246,234 @@ pub async fn upload_by_hash(
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
-    let parent_id = hash_upload_dto.parent_id;
-    let success = FileService::check_upload(
+    let (challenge, ranges) =
+        FileService::check_upload(claims.uid, &hash_upload_dto.hash, hash_upload_dto.size)?;
+    let challenge_vo = HashChallengeVo {
+        challenge,
+        ranges: ranges
+            .into_iter()
+            .map(|(start, end)| ByteRange { start, end })
+            .collect(),
+    };
+    res.render(Json(ResultData::<HashChallengeVo>::new(
+        "Check hash finished",
+        Some(challenge_vo),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(201),
+    responses(
+        (status_code = 201, description = "Verify hash challenge", body = ResultData<String>),
+    )
+)]
+pub async fn verify_upload_by_hash(
+    hash_verify_dto: JsonBody<HashVerifyDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let target = UploadTarget::resolve(
+        &claims.uid,
+        hash_verify_dto.item_id,
+        hash_verify_dto.parent_id,
+        hash_verify_dto.logic_name.clone(),
+        hash_verify_dto.path.as_deref(),
+        hash_verify_dto.conflict.unwrap_or_default(),
+    )
+    .await?;
+    FileService::verify_upload(
         claims.uid,
-        &hash_upload_dto.hash,
-        hash_upload_dto.logic_name.clone(),
-        parent_id,
+        hash_verify_dto.challenge.as_str(),
+        hash_verify_dto.digest.as_str(),
+        target,
     )
     .await?;
-    res.render(Json(ResultData::<bool>::new(
-        "Check hash finished",
-        Some(success),
+    res.render(Json(ResultData::<String>::new(
+        "Completed upload",
+        None,
         ResultCode::Success,
     )));
-    Ok(StatusCode::OK)
+    Ok(StatusCode::CREATED)
+}
+
+// Multipart framing around the file part, tolerated on top of the size limit
+const FORM_OVERHEAD: u64 = 64 * 1024;
+
+fn form_text(form: &StreamedForm, name: &str) -> Option<String> {
+    form.fields.get(name).filter(|value| !value.is_empty()).cloned()
+}
+
+fn form_uuid(form: &StreamedForm, name: &str) -> Result<Option<Uuid>, AppError> {
+    form_text(form, name)
+        .map(|id| Uuid::parse_str(&id).map_err(|_e| AppError::MissingField(name.into())))
+        .transpose()
+}
+
+fn form_conflict(form: &StreamedForm) -> Result<ConflictPolicy, AppError> {
+    match form_text(form, "conflict") {
+        Some(name) => ConflictPolicy::parse(&name).ok_or(AppError::MissingField("conflict".into())),
+        None => Ok(ConflictPolicy::default()),
+    }
+}
+
+#[endpoint(
+    status_codes(201),
+    responses(
+        (status_code = 201, description = "Upload small file", body = ResultData<String>),
+    )
+)]
+pub async fn upload_file(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let max_size = config!().upload.direct_max_mb * 1024 * 1024;
+    // Refuse oversized bodies before reading any of it
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|size| size > max_size + FORM_OVERHEAD) {
+        return Err(AppError::FileTooLarge);
+    }
+    // The file part is hashed as it streams in
+    let mut form = read_streamed_form(req, "file", max_size).await?;
+    let file = form
+        .file
+        .take()
+        .ok_or(AppError::MissingField("file".into()))?;
+    let target = UploadTarget::resolve(
+        &claims.uid,
+        form_uuid(&form, "item_id")?,
+        form_uuid(&form, "parent_id")?,
+        form_text(&form, "logic_name").or_else(|| file.name.clone()),
+        form_text(&form, "path").as_deref(),
+        form_conflict(&form)?,
+    )
+    .await?;
+    FileService::upload_small_file(claims.uid, target, &file).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Completed upload",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::CREATED)
 }
+
 #[endpoint(
     status_codes(200),
     responses(
-        (status_code = 200, description = "Start upload", body = ResultData<String>),
+        (status_code = 200, description = "Start upload", body = ResultData<StartUploadVo>),
     )
 )]
-pub async fn start_upload_file(res: &mut Response) -> Result<StatusCode, AppError> {
+pub async fn start_upload_file(
+    start_upload_dto: JsonBody<StartUploadDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    if start_upload_dto.size < 0 {
+        return Err(AppError::MissingField("size".into()));
+    }
     // After hash check, file not find, start upload
-    let file = FileService::create_new_file().await?;
-    let file_id = file
+    let session = FileService::start_upload(
+        claims.uid,
+        start_upload_dto.size,
+        start_upload_dto.part_size,
+        start_upload_dto.checksum_algorithm,
+    )
+    .await?;
+    let session_id = session
         .id
-        .ok_or(AppError::InnerError("Spawn file_id error".to_string()))?;
-    let path = file.path.clone();
-    let upload_id = FileService::start_upload(file).await?;
-    let payload = create_payload(file_id, upload_id, path, Operation::FromFileStartUpload)?;
-    res.render(Json(ResultData::<String>::new(
+        .ok_or(AppError::InnerError("Spawn session_id error".to_string()))?;
+    let part_size = session.part_size.unwrap_or_default();
+    let part_count = part_count(start_upload_dto.size, part_size);
+    // First window of part urls, later ones come from upload-part-urls. Checksummed uploads
+    // only get urls for the parts whose checksums came along
+    let checksums = start_upload_dto.checksums.clone().unwrap_or_default();
+    let parts = if start_upload_dto.checksum_algorithm.is_none() || !checksums.is_empty() {
+        FileService::get_part_upload_urls(&session, 1, part_count, &checksums)
+            .await?
+            .into_iter()
+            .map(|(part, url)| PartUrlVo { part, url })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let payload = FileService::upload_credentials(&session)?;
+    res.render(Json(ResultData::<StartUploadVo>::new(
         "Can start upload",
-        Some(payload),
+        Some(StartUploadVo {
+            session_id,
+            credentials: payload,
+            part_size,
+            part_count,
+            parts,
+        }),
         ResultCode::Success,
     )));
     Ok(StatusCode::CREATED)
 }
 
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Get unfinished uploads", body = ResultData<Vec<UploadSession>>),
+    )
+)]
+pub async fn get_upload_sessions(
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let sessions = FileService::get_upload_sessions(&claims.uid).await?;
+    res.render(Json(ResultData::<Vec<UploadSession>>::new(
+        "Get success",
+        Some(sessions),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("sid" = String, Path, description = "Upload session id")
+    ),
+    responses(
+        (status_code = 200, description = "Get uploaded parts", body = ResultData<Vec<UploadedPartVo>>),
+    )
+)]
+pub async fn get_uploaded_parts(
+    sid: QueryParam<Uuid, true>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let parts = FileService::get_uploaded_parts(&claims.uid, &sid.into_inner()).await?;
+    res.render(Json(ResultData::<Vec<UploadedPartVo>>::new(
+        "Get success",
+        Some(parts),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
 #[endpoint(
     status_codes(200),
     responses(
@@ -119,19 +482,21 @@ pub async fn start_upload_file(res: &mut Response) -> Result<StatusCode, AppErro
 )]
 pub async fn upload_file_part(
     part_upload_dto: JsonBody<PartUploadDto>,
+    depot: &mut Depot,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
-    let payload = validate_payload(part_upload_dto.credentials.as_str())?;
-    if payload.operation != Operation::FromFileStartUpload {
-        return Err(AppError::PayloadInvalid);
-    }
-    let upload_path = payload.data.clone();
-    let url = FileService::get_part_upload_url(
-        part_upload_dto.part,
-        payload.id.clone(),
-        upload_path.ok_or(AppError::PayloadInvalid)?,
-    )
-    .await?;
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let session =
+        FileService::get_upload_session(&claims.uid, part_upload_dto.credentials.as_str()).await?;
+    let checksums: Vec<String> = part_upload_dto.checksum.clone().into_iter().collect();
+    let (_, url) =
+        FileService::get_part_upload_urls(&session, part_upload_dto.part, 1, &checksums)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UploadPlanMismatch)?;
     res.render(Json(ResultData::<String>::new(
         format!("Get {} part url", part_upload_dto.part),
         Some(url),
@@ -140,6 +505,40 @@ pub async fn upload_file_part(
     Ok(StatusCode::OK)
 }
 
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Get a window of upload urls", body = ResultData<Vec<PartUrlVo>>),
+    )
+)]
+pub async fn upload_file_parts(
+    part_urls_dto: JsonBody<PartUrlsDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let session =
+        FileService::get_upload_session(&claims.uid, part_urls_dto.credentials.as_str()).await?;
+    let parts = FileService::get_part_upload_urls(
+        &session,
+        part_urls_dto.start,
+        part_urls_dto.count,
+        part_urls_dto.checksums.as_deref().unwrap_or_default(),
+    )
+    .await?
+    .into_iter()
+    .map(|(part, url)| PartUrlVo { part, url })
+    .collect();
+    res.render(Json(ResultData::<Vec<PartUrlVo>>::new(
+        "Get part urls",
+        Some(parts),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
 #[endpoint(
     status_codes(201),
     responses(
@@ -155,35 +554,26 @@ pub async fn finish_upload(
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
 
-    let payload = validate_payload(finish_uploaded_dto.credentials.as_str())?;
-    if payload.operation != Operation::FromFileStartUpload {
-        return Err(AppError::PayloadInvalid);
-    }
+    let session =
+        FileService::get_upload_session(&claims.uid, finish_uploaded_dto.credentials.as_str())
+            .await?;
 
-    let completed_parts: Vec<CompletedPart> = finish_uploaded_dto
+    let completed_parts: Vec<(i64, String, Option<String>)> = finish_uploaded_dto
         .parts
-        .clone()
-        .into_iter()
-        .map(|part| {
-            CompletedPart::builder()
-                .e_tag(part.etag)
-                .part_number(part.part as i32)
-                .build()
-        })
+        .iter()
+        .map(|part| (part.part, part.etag.clone(), part.checksum.clone()))
         .collect();
 
-    FileService::set_completed_upload(
-        claims.uid,
-        Some(payload.uid),
+    let target = UploadTarget::resolve(
+        &claims.uid,
+        finish_uploaded_dto.item_id,
         finish_uploaded_dto.parent_id,
         finish_uploaded_dto.logic_name.clone(),
-        payload
-            .data
-            .ok_or(AppError::InnerError("finish_upload error".to_string()))?,
-        &payload.id,
-        completed_parts,
+        finish_uploaded_dto.path.as_deref(),
+        finish_uploaded_dto.conflict.unwrap_or_default(),
     )
     .await?;
+    FileService::set_completed_upload(session, target, completed_parts).await?;
 
     res.render(Json(ResultData::<String>::new(
         "Completed upload",
@@ -194,6 +584,29 @@ pub async fn finish_upload(
     Ok(StatusCode::CREATED)
 }
 
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Abort upload", body = ResultData<String>),
+    )
+)]
+pub async fn abort_upload(
+    abort_upload_dto: JsonBody<AbortUploadDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::abort_upload(&claims.uid, &abort_upload_dto.session_id).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Aborted upload",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
 #[endpoint(
     status_codes(201),
     responses(
@@ -209,7 +622,13 @@ pub async fn make_logic_dir(
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
     let parent_id = make_dir_dto.parent_id;
-    FileService::mkdir_item(claims.uid, make_dir_dto.logic_name.clone(), parent_id).await?;
+    FileService::mkdir_item(
+        claims.uid,
+        make_dir_dto.logic_name.clone(),
+        parent_id,
+        make_dir_dto.conflict.unwrap_or_default(),
+    )
+    .await?;
 
     res.render(Json(ResultData::<String>::new(
         "Make dir",
@@ -221,49 +640,46 @@ pub async fn make_logic_dir(
 }
 
 #[endpoint(
-    status_codes(200),
-    parameters(
-        ("iid" = String, Path, description = "File id")
-    ),
+    status_codes(201),
     responses(
-        (status_code = 200, description = "Get download url", body = ResultData<String>),
+        (status_code = 201, description = "Make dir and any missing parents, returns the folder id", body = ResultData<Uuid>),
     )
 )]
-pub async fn download(
-    iid: QueryParam<Uuid, true>,
-    name: QueryParam<String, true>,
+pub async fn make_logic_dir_path(
+    path_dto: JsonBody<PathDto>,
+    depot: &mut Depot,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
-    let url = FileService::get_download_url(&iid, &name).await?;
-    res.render(Json(ResultData::<String>::new(
-        "Completed get download url",
-        Some(url),
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let folder_id = FileService::mkdir_path(claims.uid, &path_dto.path).await?;
+    res.render(Json(ResultData::<Uuid>::new(
+        "Make dir",
+        folder_id,
         ResultCode::Success,
     )));
-    Ok(StatusCode::OK)
+    Ok(StatusCode::CREATED)
 }
 
 #[endpoint(
     status_codes(200),
-    parameters(
-        ("iid" = String, Path, description = "File id")
-    ),
     responses(
-        (status_code = 200, description = "Delete item", body = ResultData<String>),
+        (status_code = 200, description = "Resolve a path, no data for the root", body = ResultData<Item>),
     )
 )]
-pub async fn delete(
-    iid: QueryParam<Uuid, true>,
+pub async fn resolve_path(
+    path_dto: JsonBody<PathDto>,
     depot: &mut Depot,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
-    FileService::delete_item(&claims.uid, &iid.into_inner()).await?;
-    res.render(Json(ResultData::<String>::new(
-        "Completed deleted",
-        None,
+    let item = FileService::resolve_path(&claims.uid, &path_dto.path).await?;
+    res.render(Json(ResultData::<Item>::new(
+        "Get success",
+        item,
         ResultCode::Success,
     )));
     Ok(StatusCode::OK)
@@ -271,26 +687,25 @@ pub async fn delete(
 
 #[endpoint(
     status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "Item id")
+    ),
     responses(
-        (status_code = 200, description = "Get items", body = ResultData<Vec<Item>>),
+        (status_code = 200, description = "Get size and item counts below a folder", body = ResultData<FolderStats>),
     )
 )]
-pub async fn get_item(
-    items_dto: JsonBody<ItemsDto>,
+pub async fn get_folder_stats(
+    iid: QueryParam<Uuid, true>,
     depot: &mut Depot,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
-    let item_id = items_dto.parent_id;
-    let items =
-        FileService::get_item_list(claims.uid, item_id, items_dto.page, config!().page.size)
-            .await?;
-
-    res.render(Json(ResultData::<Vec<Item>>::new(
+    let stats = FileService::get_folder_stats(&claims.uid, &iid).await?;
+    res.render(Json(ResultData::<FolderStats>::new(
         "Get success",
-        Some(items.records),
+        Some(stats),
         ResultCode::Success,
     )));
     Ok(StatusCode::OK)
@@ -299,26 +714,574 @@ pub async fn get_item(
 #[endpoint(
     status_codes(200),
     parameters(
-        ("iid" = String, Path, description = "File id"),
-        ("name" = String, Path, description = "File id")
+        ("iid" = String, Path, description = "Item id")
     ),
     responses(
-        (status_code = 200, description = "Rename item", body = ResultData<String>),
+        (status_code = 200, description = "Get the folders from the root down to the item", body = ResultData<Vec<Breadcrumb>>),
     )
 )]
-pub async fn rename(
+pub async fn get_breadcrumbs(
     iid: QueryParam<Uuid, true>,
-    name: QueryParam<String, true>,
     depot: &mut Depot,
     res: &mut Response,
 ) -> Result<StatusCode, AppError> {
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
-    FileService::rename_item(&claims.uid, &iid.into_inner(), &name.into_inner()).await?;
-    res.render(Json(ResultData::<String>::new(
-        "Completed rename",
-        None,
+    let crumbs = FileService::get_breadcrumbs(&claims.uid, &iid).await?;
+    res.render(Json(ResultData::<Vec<Breadcrumb>>::new(
+        "Get success",
+        Some(crumbs),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "File id")
+    ),
+    responses(
+        (status_code = 200, description = "Get download url", body = ResultData<String>),
+    )
+)]
+pub async fn download(
+    iid: QueryParam<Uuid, true>,
+    name: QueryParam<String, true>,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let url = FileService::get_download_url(&iid, &name).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Completed get download url",
+        Some(url),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "Item id")
+    ),
+    responses(
+        (status_code = 200, description = "Get versions", body = ResultData<Vec<ItemVersionVo>>),
+    )
+)]
+pub async fn get_versions(
+    iid: QueryParam<Uuid, true>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let versions = FileService::get_versions(&claims.uid, &iid).await?;
+    res.render(Json(ResultData::<Vec<ItemVersionVo>>::new(
+        "Get success",
+        Some(versions),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "Item id"),
+        ("vid" = String, Path, description = "Version id")
+    ),
+    responses(
+        (status_code = 200, description = "Get version download url", body = ResultData<String>),
+    )
+)]
+pub async fn download_version(
+    iid: QueryParam<Uuid, true>,
+    vid: QueryParam<Uuid, true>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let url = FileService::get_version_download_url(&claims.uid, &iid, &vid).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Completed get download url",
+        Some(url),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Restore version", body = ResultData<String>),
+    )
+)]
+pub async fn restore_version(
+    restore_version_dto: JsonBody<RestoreVersionDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::restore_version(
+        &claims.uid,
+        &restore_version_dto.item_id,
+        &restore_version_dto.version_id,
+    )
+    .await?;
+    res.render(Json(ResultData::<String>::new(
+        "Restore success",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200, 206, 304, 416),
+    parameters(
+        ("iid" = String, Path, description = "Item id")
+    ),
+    responses(
+        (status_code = 200, description = "Whole file"),
+        (status_code = 206, description = "Requested byte range"),
+        (status_code = 304, description = "Client copy still valid"),
+        (status_code = 416, description = "Range not satisfiable"),
+    )
+)]
+pub async fn stream_download(
+    iid: QueryParam<Uuid, true>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let (item, file, head) = FileService::get_owned_file(&claims.uid, &iid.into_inner()).await?;
+    let etag = file.sha_256.as_ref().map(|hash| format!("\"{}\"", hash));
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    // If-None-Match wins over If-Modified-Since when both are sent
+    let not_modified = match (header(IF_NONE_MATCH), etag.as_deref(), file.create_time) {
+        (Some(tags), Some(etag), _) => etag_matches(tags, etag),
+        (Some(_), None, _) => false,
+        (None, _, Some(modified)) => {
+            header(IF_MODIFIED_SINCE).is_some_and(|since| not_modified_since(since, &modified))
+        }
+        (None, _, None) => false,
+    };
+    let range = parse_range(header(RANGE), head.size);
+
+    res.add_header(ACCEPT_RANGES, "bytes", true)
+        .and_then(|res| res.add_header(CACHE_CONTROL, "private, no-cache", true))
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    if let Some(etag) = etag {
+        res.add_header(ETAG, etag, true)
+            .map_err(|e| AppError::InnerError(e.to_string()))?;
+    }
+    if let Some(modified) = file.create_time {
+        res.add_header(LAST_MODIFIED, http_date(&modified), true)
+            .map_err(|e| AppError::InnerError(e.to_string()))?;
+    }
+    if not_modified {
+        return Ok(StatusCode::NOT_MODIFIED);
+    }
+
+    let (status, body_range) = match range {
+        RangeRequest::Unsatisfiable => {
+            res.add_header(CONTENT_RANGE, format!("bytes */{}", head.size), true)
+                .map_err(|e| AppError::InnerError(e.to_string()))?;
+            return Ok(StatusCode::RANGE_NOT_SATISFIABLE);
+        }
+        RangeRequest::Satisfiable(start, end) => {
+            res.add_header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, head.size),
+                true,
+            )
+            .and_then(|res| res.add_header(CONTENT_LENGTH, end - start + 1, true))
+            .map_err(|e| AppError::InnerError(e.to_string()))?;
+            (StatusCode::PARTIAL_CONTENT, Some((start, end)))
+        }
+        RangeRequest::Full => {
+            res.add_header(CONTENT_LENGTH, head.size, true)
+                .map_err(|e| AppError::InnerError(e.to_string()))?;
+            (StatusCode::OK, None)
+        }
+    };
+    let body = FileService::read_file(&file, body_range).await?;
+    res.add_header(
+        CONTENT_TYPE,
+        file.file_type
+            .unwrap_or_else(|| "application/octet-stream".to_string()),
+        true,
+    )
+    .and_then(|res| {
+        res.add_header(
+            CONTENT_DISPOSITION,
+            content_disposition("attachment", item.logic_name.as_deref().unwrap_or_default()),
+            true,
+        )
+    })
+    .map_err(|e| AppError::InnerError(e.to_string()))?;
+    res.stream(into_body_stream(body));
+    Ok(status)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "ZIP archive of the selected items"),
+    )
+)]
+pub async fn download_archive(
+    archive_dto: JsonBody<ArchiveDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    if archive_dto.item_ids.is_empty() {
+        return Err(AppError::MissingField("item_ids".into()));
+    }
+    let entries = FileService::collect_archive_entries(&claims.uid, &archive_dto.item_ids).await?;
+    let archive_name = match entries.first() {
+        Some(entry) if archive_dto.item_ids.len() == 1 => {
+            format!("{}.zip", entry.path.trim_end_matches('/'))
+        }
+        _ => "archive.zip".to_string(),
+    };
+
+    // The archive is written into one end of a pipe while the response drains the other. A
+    // failed write ends the body with an error so the client sees a broken transfer
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let task = tokio::spawn(FileService::write_archive(entries, writer));
+    let outcome = futures::stream::once(async move {
+        let e = match task.await {
+            Ok(Ok(())) => return None,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        error!("write archive fail, E: {}", e);
+        Some(Err(std::io::Error::other(e)))
+    })
+    .filter_map(futures::future::ready);
+    res.add_header(CONTENT_TYPE, "application/zip", true)
+        .and_then(|res| {
+            res.add_header(
+                CONTENT_DISPOSITION,
+                content_disposition("attachment", &archive_name),
+                true,
+            )
+        })
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    res.stream(ReaderStream::new(reader).chain(outcome));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "File id")
+    ),
+    responses(
+        (status_code = 200, description = "Delete item", body = ResultData<String>),
+    )
+)]
+pub async fn delete(
+    iid: QueryParam<Uuid, true>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::delete_item(&claims.uid, &iid.into_inner()).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Completed deleted",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(202),
+    responses(
+        (status_code = 202, description = "Start copying items", body = ResultData<CopyJob>),
+    )
+)]
+pub async fn copy_items(
+    copy_items_dto: JsonBody<CopyItemsDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    if copy_items_dto.item_ids.is_empty() {
+        return Err(AppError::MissingField("item_ids".into()));
+    }
+    let job = FileService::copy_items(
+        claims.uid,
+        &copy_items_dto.item_ids,
+        copy_items_dto.parent_id,
+        copy_items_dto.conflict.unwrap_or_default(),
+    )
+    .await?;
+    res.render(Json(ResultData::<CopyJob>::new(
+        "Copy started",
+        Some(job),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("jid" = String, Path, description = "Copy job id")
+    ),
+    responses(
+        (status_code = 200, description = "Get copy progress", body = ResultData<CopyJob>),
+    )
+)]
+pub async fn get_copy_job(
+    jid: QueryParam<Uuid, true>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let job = FileService::get_copy_job(&claims.uid, &jid).await?;
+    res.render(Json(ResultData::<CopyJob>::new(
+        "Get success",
+        Some(job),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Get trash", body = ResultData<Vec<TrashVo>>),
+    )
+)]
+pub async fn get_trash(depot: &mut Depot, res: &mut Response) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let trash = FileService::get_trash(&claims.uid).await?;
+    res.render(Json(ResultData::<Vec<TrashVo>>::new(
+        "Get success",
+        Some(trash),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Restore from trash", body = ResultData<String>),
+    )
+)]
+pub async fn restore_trash(
+    trash_dto: JsonBody<TrashDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::restore_trash(&claims.uid, &trash_dto.trash_id).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Restore success",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Purge from trash", body = ResultData<String>),
+    )
+)]
+pub async fn purge_trash(
+    trash_dto: JsonBody<TrashDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::purge_trash(&claims.uid, &trash_dto.trash_id).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Purge success",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Empty trash, returns how many deletions were purged", body = ResultData<u64>),
+    )
+)]
+pub async fn empty_trash(depot: &mut Depot, res: &mut Response) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let count = FileService::empty_trash(&claims.uid).await?;
+    res.render(Json(ResultData::<u64>::new(
+        "Purge success",
+        Some(count),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Get items, a CursorPage<ItemVo> when a cursor is sent", body = ResultData<ItemPageVo>),
+    )
+)]
+pub async fn get_item(
+    items_dto: JsonBody<ItemsDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let item_id = match &items_dto.path {
+        Some(path) => FileService::resolve_folder_path(&claims.uid, path).await?,
+        None => items_dto.parent_id,
+    };
+    let order = ItemOrder {
+        sort: items_dto.sort.unwrap_or_default(),
+        order: items_dto.order.unwrap_or_default(),
+        folders_first: items_dto.folders_first.unwrap_or(true),
+    };
+    if let Some(cursor) = &items_dto.cursor {
+        let items = FileService::get_item_cursor(
+            claims.uid,
+            item_id,
+            cursor,
+            page_size(items_dto.page_size),
+            order,
+        )
+        .await?;
+        res.render(Json(ResultData::<CursorPage<ItemVo>>::new(
+            "Get success",
+            Some(items),
+            ResultCode::Success,
+        )));
+        return Ok(StatusCode::OK);
+    }
+    let items = FileService::get_item_list(
+        claims.uid,
+        item_id,
+        items_dto.page.unwrap_or(1),
+        page_size(items_dto.page_size),
+        order,
+    )
+    .await?;
+
+    res.render(Json(ResultData::<ItemPageVo>::new(
+        "Get success",
+        Some(items.into()),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Search items", body = ResultData<ItemPageVo>),
+    )
+)]
+pub async fn search_items(
+    search_dto: JsonBody<SearchDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let search_dto = search_dto.into_inner();
+    let search = ItemSearch {
+        query: search_dto.query,
+        mode: search_dto.mode.unwrap_or_default(),
+        is_folder: search_dto.is_folder,
+        file_type: search_dto.file_type,
+        min_size: search_dto.min_size,
+        max_size: search_dto.max_size,
+        created_after: search_dto.created_after,
+        created_before: search_dto.created_before,
+        root_id: search_dto.root_id,
+    };
+    let items = FileService::search_items(
+        &claims.uid,
+        &search,
+        search_dto.page,
+        page_size(search_dto.page_size),
+    )
+    .await?;
+    res.render(Json(ResultData::<ItemPageVo>::new(
+        "Get success",
+        Some(items.into()),
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "File id"),
+        ("name" = String, Path, description = "File id"),
+        ("conflict" = Option<ConflictPolicy>, Query, description = "fail, rename or overwrite")
+    ),
+    responses(
+        (status_code = 200, description = "Rename item", body = ResultData<String>),
+    )
+)]
+pub async fn rename(
+    iid: QueryParam<Uuid, true>,
+    name: QueryParam<String, true>,
+    conflict: QueryParam<ConflictPolicy, false>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    FileService::rename_item(
+        &claims.uid,
+        &iid.into_inner(),
+        &name.into_inner(),
+        conflict.into_inner().unwrap_or_default(),
+    )
+    .await?;
+    res.render(Json(ResultData::<String>::new(
+        "Completed rename",
+        None,
         ResultCode::Success,
     )));
     Ok(StatusCode::OK)
@@ -338,7 +1301,13 @@ pub async fn move_item(
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
-    FileService::move_item(&claims.uid, &move_item_dto.item_id, move_item_dto.parent_id).await?;
+    FileService::move_item(
+        &claims.uid,
+        &move_item_dto.item_id,
+        move_item_dto.parent_id,
+        move_item_dto.conflict.unwrap_or_default(),
+    )
+    .await?;
     res.render(Json(ResultData::<String>::new(
         "Completed move",
         None,
@@ -346,3 +1315,95 @@ pub async fn move_item(
     )));
     Ok(StatusCode::OK)
 }
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("token" = String, Path, description = "Part upload token")
+    ),
+    responses(
+        (status_code = 200, description = "Put part to local storage"),
+    )
+)]
+pub async fn put_storage_part(
+    token: QueryParam<String, true>,
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let target = validate_part_token(token.as_str())?;
+    FileService::check_part_target(&target).await?;
+    let body = ByteStream::from_body_1_x(req.take_body());
+    let etag = storage!()
+        .upload_part(
+            &target.bucket,
+            &target.key,
+            &target.upload_id,
+            target.part,
+            body,
+            target.checksum.as_ref(),
+        )
+        .await?;
+    // Same contract as a presigned S3 part upload, the client reads the etag header
+    res.add_header(ETAG, format!("\"{}\"", etag), true)
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("token" = String, Path, description = "Download token")
+    ),
+    responses(
+        (status_code = 200, description = "Get object from local storage"),
+    )
+)]
+pub async fn get_storage_object(
+    token: QueryParam<String, true>,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let target = validate_object_token(token.as_str())?;
+    let head = storage!().head(&target.bucket, &target.key).await?;
+    let body = storage!().get(&target.bucket, &target.key).await?;
+    res.add_header(CONTENT_TYPE, target.content_type, true)
+        .and_then(|res| res.add_header(CONTENT_LENGTH, head.size, true))
+        .and_then(|res| {
+            res.add_header(
+                CONTENT_DISPOSITION,
+                content_disposition("attachment", &target.file_name),
+                true,
+            )
+        })
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    res.stream(into_body_stream(body));
+    Ok(StatusCode::OK)
+}
+
+#[endpoint(
+    status_codes(200),
+    parameters(
+        ("iid" = String, Path, description = "Item id"),
+        ("size" = u32, Path, description = "Wanted edge length, optional")
+    ),
+    responses(
+        (status_code = 200, description = "Get item thumbnail"),
+    )
+)]
+pub async fn thumbnail(
+    iid: QueryParam<Uuid, true>,
+    size: QueryParam<u32, false>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    let (length, body) =
+        FileService::get_thumbnail(&claims.uid, &iid.into_inner(), size.into_inner()).await?;
+    res.add_header(CONTENT_TYPE, "image/jpeg", true)
+        .and_then(|res| res.add_header(CONTENT_LENGTH, length, true))
+        .and_then(|res| res.add_header(CACHE_CONTROL, "private, max-age=86400", true))
+        .map_err(|e| AppError::InnerError(e.to_string()))?;
+    res.stream(into_body_stream(body));
+    Ok(StatusCode::OK)
+}
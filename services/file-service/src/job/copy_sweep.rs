@@ -0,0 +1,20 @@
+use crate::service::file_service::FileService;
+use common::config;
+use std::time::Duration;
+use tracing::{error, info};
+
+// The first tick runs at startup and fails what the previous process left running
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config!().copy.stale_min * 60));
+        loop {
+            interval.tick().await;
+            match FileService::fail_stale_copies(config!().copy.stale_min as i64).await {
+                Ok(0) => {}
+                Ok(count) => info!("copy sweep failed {} stale jobs", count),
+                Err(e) => error!("copy sweep fail, E: {}", e),
+            }
+        }
+    });
+}
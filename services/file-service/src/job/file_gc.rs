@@ -0,0 +1,24 @@
+use crate::service::file_service::FileService;
+use common::config;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config!().gc.interval_min * 60));
+        loop {
+            interval.tick().await;
+            match FileService::collect_unreferenced_files(
+                config!().gc.grace_min as i64,
+                config!().gc.batch_size as i64,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(count) => info!("file gc removed {} files", count),
+                Err(e) => error!("file gc fail, E: {}", e),
+            }
+        }
+    });
+}
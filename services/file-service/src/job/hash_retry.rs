@@ -0,0 +1,19 @@
+use crate::service::file_service::FileService;
+use common::config;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config!().upload.hash_retry_min * 60));
+        loop {
+            interval.tick().await;
+            match FileService::retry_after_upload(config!().upload.hash_retry_min as i64).await {
+                Ok(0) => {}
+                Ok(count) => info!("hash retry finished {} sessions", count),
+                Err(e) => error!("hash retry fail, E: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,5 @@
+pub mod copy_sweep;
+pub mod file_gc;
+pub mod hash_retry;
+pub mod trash_purge;
+pub mod upload_reaper;
@@ -0,0 +1,24 @@
+use crate::service::file_service::FileService;
+use common::config;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config!().trash.purge_interval_min * 60));
+        loop {
+            interval.tick().await;
+            match FileService::purge_expired_trash(
+                config!().trash.retention_days as i64,
+                config!().trash.batch_size as i64,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(count) => info!("trash purge removed {} deletions", count),
+                Err(e) => error!("trash purge fail, E: {}", e),
+            }
+        }
+    });
+}
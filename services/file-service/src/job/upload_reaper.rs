@@ -0,0 +1,19 @@
+use crate::service::file_service::FileService;
+use common::config;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(config!().upload.reap_interval_min * 60));
+        loop {
+            interval.tick().await;
+            match FileService::reap_expired_uploads(config!().upload.session_ttl_min as i64).await {
+                Ok(0) => {}
+                Ok(count) => info!("upload reaper aborted {} sessions", count),
+                Err(e) => error!("upload reaper fail, E: {}", e),
+            }
+        }
+    });
+}
@@ -1,4 +1,5 @@
 mod handler;
+mod job;
 mod router;
 mod service;
 
@@ -24,7 +25,12 @@ async fn main() {
 
     let config = &CONTEXT.config;
     CONTEXT.init_database().await;
-    CONTEXT.init_minio().await;
+    CONTEXT.init_storage().await;
+    job::upload_reaper::spawn();
+    job::file_gc::spawn();
+    job::hash_retry::spawn();
+    job::trash_purge::spawn();
+    job::copy_sweep::spawn();
 
     let router = openapi(
         Router::new().push(all_router()),
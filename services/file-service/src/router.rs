@@ -6,14 +6,39 @@ pub fn all_router() -> Router {
     Router::with_path("api").push(
         Router::with_path("file")
             .push(Router::with_path("upload-by-hash").hoop(auth_middleware).hoop(check_size).put(upload_by_hash))
+            .push(Router::with_path("upload-by-hash-verify").hoop(auth_middleware).hoop(check_size).post(verify_upload_by_hash))
+            .push(Router::with_path("upload").hoop(auth_middleware).hoop(check_size).post(upload_file))
             .push(Router::with_path("start-upload").hoop(auth_middleware).hoop(check_size).put(start_upload_file))
             .push(Router::with_path("upload-part").hoop(auth_middleware).put(upload_file_part))
+            .push(Router::with_path("upload-part-urls").hoop(auth_middleware).put(upload_file_parts))
+            .push(Router::with_path("upload-sessions").hoop(auth_middleware).get(get_upload_sessions))
+            .push(Router::with_path("upload-parts{**}").hoop(auth_middleware).get(get_uploaded_parts))
+            .push(Router::with_path("abort-upload").hoop(auth_middleware).post(abort_upload))
             .push(Router::with_path("finish-upload").hoop(auth_middleware).hoop(check_size).post(finish_upload))
             .push(Router::with_path("mkdir").hoop(auth_middleware).put(make_logic_dir))
+            .push(Router::with_path("mkdir-path").hoop(auth_middleware).put(make_logic_dir_path))
+            .push(Router::with_path("resolve").hoop(auth_middleware).post(resolve_path))
+            .push(Router::with_path("folder-stats{**}").hoop(auth_middleware).get(get_folder_stats))
+            .push(Router::with_path("breadcrumbs{**}").hoop(auth_middleware).get(get_breadcrumbs))
             .push(Router::with_path("get").hoop(auth_middleware).post(get_item))
+            .push(Router::with_path("search").hoop(auth_middleware).post(search_items))
             .push(Router::with_path("move").hoop(auth_middleware).post(move_item))
+            .push(Router::with_path("copy").hoop(auth_middleware).post(copy_items))
+            .push(Router::with_path("copy-job{**}").hoop(auth_middleware).get(get_copy_job))
             .push(Router::with_path("download{**}").hoop(auth_middleware).get(download))
+            .push(Router::with_path("versions{**}").hoop(auth_middleware).get(get_versions))
+            .push(Router::with_path("version-download{**}").hoop(auth_middleware).get(download_version))
+            .push(Router::with_path("version-restore").hoop(auth_middleware).post(restore_version))
+            .push(Router::with_path("stream{**}").hoop(auth_middleware).get(stream_download))
+            .push(Router::with_path("archive").hoop(auth_middleware).post(download_archive))
             .push(Router::with_path("delete{**}").hoop(auth_middleware).delete(delete))
+            .push(Router::with_path("trash").hoop(auth_middleware).get(get_trash))
+            .push(Router::with_path("trash-restore").hoop(auth_middleware).post(restore_trash))
+            .push(Router::with_path("trash-purge").hoop(auth_middleware).post(purge_trash))
+            .push(Router::with_path("trash-empty").hoop(auth_middleware).post(empty_trash))
             .push(Router::with_path("rename{**}").hoop(auth_middleware).post(rename))
+            .push(Router::with_path("thumbnail{**}").hoop(auth_middleware).get(thumbnail))
+            .push(Router::with_path("storage/part{**}").put(put_storage_part))
+            .push(Router::with_path("storage/object{**}").get(get_storage_object))
     )
 }
\ No newline at end of file
@@ -1,153 +1,1118 @@
-use aws_sdk_s3::types::CompletedPart;
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use aws_sdk_s3::primitives::ByteStream;
+use common::module::copy_job::{CopyJob, CopyStatus};
 use common::module::error::AppError;
 use common::module::file::File;
-use common::module::item::Item;
-use common::util::hash::get_size_and_hash;
-use common::util::minio::{
-    complete_upload, generate_download_url, generate_part_upload_url, generate_upload_id,
+use common::module::item::{
+    Breadcrumb, ConflictPolicy, FolderStats, Item, ItemOrder, ItemSearch, ItemVo, NameSlot,
+    TreeSize,
 };
-use common::{config, db_pool, minio_client, req_client};
+use common::module::item_version::{ItemVersion, ItemVersionVo};
+use common::module::trash::{Trash, TrashVo};
+use common::module::upload_session::{UploadSession, UploadStatus, UploadedPartVo};
+use common::module::user::User;
+use common::util::cursor::CursorPage;
+use common::util::database::begin_tx;
+use common::util::form::SpooledFile;
+use common::util::hash::{challenge_ranges, get_size_and_hash, hash_chunks};
+use common::util::jwt::{create_payload, validate_payload, Operation};
+use common::util::local_storage::LocalPartTarget;
+use common::util::mime::{sniff_mime, SNIFF_LEN};
+use common::util::multipart::{expected_part_size, part_count, plan_part_size, MAX_PARTS};
+use common::util::path::{numbered_name, path_segments, FilePathInfo};
+use common::util::storage::{
+    ChecksumAlgorithm, ObjectHead, PartChecksum, StoredPart, UploadOwner, UploadedPart,
+};
+use common::util::thumbnail::{
+    generate_thumbnails, is_thumbnail_source, pick_thumbnail_size, thumbnail_key,
+};
+use common::{config, db_pool, storage};
+use futures::AsyncWriteExt;
+use rbatis::executor::Executor;
 use rbatis::{Page, PageRequest};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{LazyLock, Mutex};
 use tracing::{error, info};
 use uuid::Uuid;
 
 pub struct FileService {}
 
-impl FileService {
-    // pub async fn check_parent_id(
-    //     path: &FilePathInfo,
-    //     user_id: Uuid,
-    // ) -> Result<Option<Uuid>, AppError> {
-    //     // let path = FilePathInfo::from_str(path)?;
-    //     // Path check
-    //     let parent_id = if path.parent != "/" {
-    //         let p_items =
-    //             Item::select_path_by_logic_name(db_pool!(), &path.parent, &user_id).await?;
-    //         let p_item = p_items.get(0).ok_or(AppError::PathOrNameError)?;
-    //         Some(
-    //             p_item
-    //                 .id
-    //                 .ok_or_else(|| AppError::InnerError("upload-upload-get_item_id".into()))?,
-    //         )
-    //     } else {
-    //         None
-    //     };
-    //     Ok(parent_id)
-    // }
-    pub async fn check_upload(
-        user_id: Uuid,
-        sha_256: &String,
+// Copy jobs write their progress back every this many items
+const COPY_PROGRESS_STEP: i64 = 100;
+
+// Recursive folder stats by (user, folder), with the user's tree stamp they were counted under
+type StatsCache = HashMap<(Uuid, Uuid), (i64, FolderStats)>;
+
+static FOLDER_STATS: LazyLock<Mutex<StatsCache>> = LazyLock::new(Default::default);
+
+// Past this many entries the cache starts over
+const FOLDER_STATS_CAP: usize = 10_000;
+
+// Where an uploaded file lands, a new item or the next version of an existing one
+pub enum UploadTarget {
+    New {
+        parent_id: Option<Uuid>,
         logic_name: String,
+        conflict: ConflictPolicy,
+    },
+    Version(Uuid),
+}
+
+impl UploadTarget {
+    // A new version keeps the item's name. Otherwise a full path stands in for parent_id and
+    // logic_name, its folders must exist
+    pub async fn resolve(
+        user_id: &Uuid,
+        item_id: Option<Uuid>,
         parent_id: Option<Uuid>,
-    ) -> Result<bool, AppError> {
-        let files = File::select_by_hash(db_pool!(), sha_256).await?;
-        if let Some(file) = files.get(0) {
-            // Hash check, file find
-            let file_id = file
-                .id
-                .ok_or_else(|| AppError::InnerError("upload-get_file_id".into()))?;
-            let item = Item::new(user_id, Some(file_id), parent_id, false, logic_name, true);
-            Item::insert(db_pool!(), &item).await?;
-        } else {
-            return Ok(false);
+        logic_name: Option<String>,
+        path: Option<&str>,
+        conflict: ConflictPolicy,
+    ) -> Result<Self, AppError> {
+        if let Some(item_id) = item_id {
+            return Ok(UploadTarget::Version(item_id));
         }
-        Ok(true)
+        let (parent_id, logic_name) = match path.filter(|path| !path.is_empty()) {
+            Some(path) => {
+                let path = FilePathInfo::from_str(path)?;
+                let parent_id = FileService::resolve_folder_path(user_id, &path.parent).await?;
+                (parent_id, Some(path.file_name))
+            }
+            None => (parent_id, logic_name),
+        };
+        let logic_name = logic_name
+            .filter(|name| !name.is_empty())
+            .ok_or(AppError::MissingField("logic_name".into()))?;
+        Ok(UploadTarget::New {
+            parent_id,
+            logic_name,
+            conflict,
+        })
     }
+}
 
-    pub async fn start_upload(file: File) -> Result<String, AppError> {
-        let minio_path = file.path.ok_or(AppError::FileNotExists)?;
-        generate_upload_id(
-            minio_client!(),
-            config!().minio.file_bucket.as_str(),
-            minio_path.as_str(),
-        )
-        .await
+// One ZIP entry, folders end with '/' and carry no object
+pub struct ArchiveEntry {
+    pub path: String,
+    pub object: Option<String>,
+}
+
+// Logic names may hold anything, keep entries inside the archive and unique
+fn archive_entry_name(used: &mut HashSet<String>, prefix: &str, logic_name: &str) -> String {
+    let name = match logic_name.replace(['/', '\\'], "_") {
+        name if name.is_empty() || name == "." || name == ".." => "_".to_string(),
+        name => name,
+    };
+    let mut candidate = format!("{}{}", prefix, name);
+    let mut n = 1;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{}{}", prefix, numbered_name(&name, n));
+        n += 1;
     }
+    candidate
+}
 
-    pub async fn get_part_upload_url(
-        part: i64,
-        upload_id: String,
-        path: String,
-    ) -> Result<String, AppError> {
-        generate_part_upload_url(
-            minio_client!(),
-            config!().minio.file_bucket.as_str(),
-            path.as_str(),
-            part,
-            upload_id,
-        )
-        .await
+impl FileService {
+    // The ranges come from the size the client declares and the store is not consulted, so the
+    // challenge says nothing about whether the hash is known. Empty content proves nothing
+    pub fn check_upload(
+        user_id: Uuid,
+        sha_256: &str,
+        size: i64,
+    ) -> Result<(String, Vec<(i64, i64)>), AppError> {
+        if size <= 0 {
+            return Err(AppError::InvalidValue("size".into()));
+        }
+        let ranges = challenge_ranges(size);
+        let data = ranges
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect::<Vec<_>>()
+            .join(",");
+        let challenge = create_payload(
+            user_id,
+            sha_256.to_string(),
+            Some(format!("{}:{}", size, data)),
+            Operation::HashChallenge,
+        )?;
+        Ok((challenge, ranges))
     }
 
-    pub async fn set_completed_upload(
+    pub async fn verify_upload(
         user_id: Uuid,
-        file_id: Option<Uuid>,
-        parent_id: Option<Uuid>,
-        logic_name: String,
-        server_path: String,
-        upload_id: &String,
-        parts: Vec<CompletedPart>,
+        challenge: &str,
+        digest: &str,
+        target: UploadTarget,
     ) -> Result<(), AppError> {
-        complete_upload(
-            minio_client!(),
-            config!().minio.file_bucket.as_str(),
-            format!("/{}", server_path.as_str()).as_str(),
+        let payload = validate_payload(challenge)?;
+        if payload.operation != Operation::HashChallenge || payload.uid != user_id {
+            return Err(AppError::PayloadInvalid);
+        }
+        let data = payload.data.unwrap_or_default();
+        let (size, ranges) = data.split_once(':').ok_or(AppError::PayloadInvalid)?;
+        let size = size.parse::<i64>().map_err(|_e| AppError::PayloadInvalid)?;
+        let ranges = ranges
+            .split(',')
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                let (start, end) = range.split_once('-').ok_or(AppError::PayloadInvalid)?;
+                Ok((
+                    start
+                        .parse::<i64>()
+                        .map_err(|_e| AppError::PayloadInvalid)?,
+                    end.parse::<i64>().map_err(|_e| AppError::PayloadInvalid)?,
+                ))
+            })
+            .collect::<Result<Vec<(i64, i64)>, AppError>>()?;
+        // An unknown hash fails like a wrong digest
+        let file = File::select_by_hash(db_pool!(), &payload.id)
+            .await?
+            .into_iter()
+            .next()
+            .filter(|file| file.size == Some(size))
+            .ok_or(AppError::HashChallengeMismatch)?;
+        let file_id = file.id.ok_or(AppError::FileNotExists)?;
+        let file_path = file.path.clone().ok_or(AppError::FileNotExists)?;
+
+        let mut chunks = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            chunks.push(
+                storage!()
+                    .get_range(
+                        config!().minio.file_bucket.as_str(),
+                        file_path.as_str(),
+                        start,
+                        end,
+                    )
+                    .await?,
+            );
+        }
+        if !hash_chunks(&chunks).eq_ignore_ascii_case(digest) {
+            return Err(AppError::HashChallengeMismatch);
+        }
+
+        // The ranged reads above ran unlocked, GC may have taken the file meanwhile
+        let tx = begin_tx(db_pool!()).await?;
+        let file = File::select_for_share(&tx, &file_id).await?;
+        User::reserve_total_size_by_id(&tx, &user_id, &file.size.unwrap_or_default()).await?;
+        let versioned = Self::attach_file(&tx, user_id, file_id, &target).await?;
+        tx.commit().await?;
+        Self::after_attach(&user_id, versioned).await;
+        Ok(())
+    }
+
+    // Small files arrive whole in one form, only content the server has not seen gets stored
+    pub async fn upload_small_file(
+        user_id: Uuid,
+        target: UploadTarget,
+        upload: &SpooledFile,
+    ) -> Result<(), AppError> {
+        let size = upload.size;
+        if let Some(file) = File::select_by_hash(db_pool!(), &upload.sha_256)
+            .await?
+            .into_iter()
+            .next()
+        {
+            let file_id = file.id.ok_or(AppError::FileNotExists)?;
+            let tx = begin_tx(db_pool!()).await?;
+            let file = File::select_for_share(&tx, &file_id).await?;
+            User::reserve_total_size_by_id(&tx, &user_id, &file.size.unwrap_or(size)).await?;
+            let versioned = Self::attach_file(&tx, user_id, file_id, &target).await?;
+            tx.commit().await?;
+            Self::after_attach(&user_id, versioned).await;
+            return Ok(());
+        }
+
+        // Store first under a key nobody knows yet, the user row is only locked for the short
+        // transaction after it. A failed transaction takes the object back out
+        let file_type = sniff_mime(&upload.head);
+        let file_id = Uuid::new_v4();
+        let path = Uuid::new_v4().to_string();
+        let bucket = config!().minio.file_bucket.as_str();
+        storage!()
+            .put(
+                bucket,
+                path.as_str(),
+                ByteStream::from_path(&upload.path)
+                    .await
+                    .map_err(|e| AppError::StorageError(e.to_string()))?,
+            )
+            .await?;
+        let file = File::uploaded_new(
+            file_id,
+            upload.sha_256.clone(),
+            path.clone(),
+            file_type.clone(),
+            size,
+        );
+        let attached = async {
+            let tx = begin_tx(db_pool!()).await?;
+            User::reserve_total_size_by_id(&tx, &user_id, &size).await?;
+            File::insert(&tx, &file).await?;
+            let versioned = Self::attach_file(&tx, user_id, file_id, &target).await?;
+            tx.commit().await?;
+            Ok::<_, AppError>(versioned)
+        }
+        .await;
+        let versioned = match attached {
+            Ok(versioned) => versioned,
+            Err(e) => {
+                if let Err(e) = storage!().delete(bucket, path.as_str()).await {
+                    error!("delete object {} fail, E: {}", path, e);
+                }
+                return Err(e);
+            }
+        };
+        Self::after_attach(&user_id, versioned).await;
+
+        // A missing thumbnail must not fail an upload that is already stored
+        match Self::generate_thumbnails(path.as_str(), size, file_type.as_str()).await {
+            Ok(Some(thumbnail)) => {
+                if let Err(e) = File::update_thumbnail(db_pool!(), &file_id, &thumbnail).await {
+                    error!("save thumbnail {} fail, E: {}", path, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("generate thumbnail {} fail, E: {}", path, e),
+        }
+        Ok(())
+    }
+
+    pub async fn start_upload(
+        user_id: Uuid,
+        size: i64,
+        part_size: Option<i64>,
+        checksum_algorithm: Option<ChecksumAlgorithm>,
+    ) -> Result<UploadSession, AppError> {
+        let part_size = plan_part_size(
+            size,
+            part_size.unwrap_or((config!().upload.part_size_mb * 1024 * 1024) as i64),
+        )
+        .ok_or(AppError::FileTooLarge)?;
+        // The row carries the declared size while hashing so trash, copy and share charge what
+        // the reservation holds, after_upload replaces it with the stored size
+        let file = File {
+            size: Some(size),
+            ..File::new()
+        };
+        let file_id = file.id.ok_or(AppError::FileNotExists)?;
+        let minio_path = file.path.clone().ok_or(AppError::FileNotExists)?;
+        // Reserve the declared size up front, after_upload settles the difference
+        let tx = begin_tx(db_pool!()).await?;
+        User::reserve_total_size_by_id(&tx, &user_id, &size).await?;
+        File::insert(&tx, &file).await?;
+        let upload_id = storage!()
+            .create_multipart(
+                config!().minio.file_bucket.as_str(),
+                minio_path.as_str(),
+                checksum_algorithm,
+            )
+            .await?;
+        let session = UploadSession::new(
+            user_id,
+            file_id,
             upload_id,
-            parts,
+            minio_path,
+            size,
+            part_size,
+            checksum_algorithm,
+        );
+        UploadSession::insert(&tx, &session).await?;
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    pub fn upload_credentials(session: &UploadSession) -> Result<String, AppError> {
+        create_payload(
+            session.user_id.ok_or(AppError::UserNotExists)?,
+            session
+                .id
+                .ok_or(AppError::UploadSessionNotExists)?
+                .to_string(),
+            None,
+            Operation::FromFileStartUpload,
+        )
+    }
+
+    // Credentials only name the issuing user and the session, the session row decides whether
+    // they still hold, so finishing or aborting revokes them
+    pub async fn get_upload_session(
+        user_id: &Uuid,
+        credentials: &str,
+    ) -> Result<UploadSession, AppError> {
+        let payload = validate_payload(credentials)?;
+        if payload.operation != Operation::FromFileStartUpload || payload.uid != *user_id {
+            return Err(AppError::PayloadInvalid);
+        }
+        let session_id = Uuid::parse_str(&payload.id).map_err(|_e| AppError::PayloadInvalid)?;
+        let session = UploadSession::select_by_id_userid(db_pool!(), &session_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        if session.status != Some(UploadStatus::Uploading as i8) {
+            return Err(AppError::UploadSessionNotExists);
+        }
+        Ok(session)
+    }
+
+    // A proxied part url only writes into the live session it was issued for
+    pub async fn check_part_target(target: &LocalPartTarget) -> Result<(), AppError> {
+        let session = UploadSession::select_by_id_userid(
+            db_pool!(),
+            &target.owner.session_id,
+            &target.owner.user_id,
+        )
+        .await?
+        .into_iter()
+        .next()
+        .filter(|session| session.status == Some(UploadStatus::Uploading as i8))
+        .ok_or(AppError::UploadSessionNotExists)?;
+        if session.upload_id.as_deref() != Some(target.upload_id.as_str())
+            || session.path.as_deref() != Some(target.key.as_str())
+        {
+            return Err(AppError::PayloadInvalid);
+        }
+        Ok(())
+    }
+
+    pub async fn get_upload_sessions(user_id: &Uuid) -> Result<Vec<UploadSession>, AppError> {
+        let sessions = UploadSession::select_by_userid_status(
+            db_pool!(),
+            user_id,
+            UploadStatus::Uploading as i8,
         )
         .await?;
-        tokio::spawn(async move {
-            match Self::after_upload(user_id, file_id, parent_id, logic_name, &server_path).await {
-                Ok(item_id) => {
-                    info!(
-                        "{} upload finish, user: {}, item: {}",
-                        server_path, user_id, item_id
-                    )
+        Ok(sessions)
+    }
+
+    pub async fn get_uploaded_parts(
+        user_id: &Uuid,
+        session_id: &Uuid,
+    ) -> Result<Vec<UploadedPartVo>, AppError> {
+        let session = UploadSession::select_by_id_userid(db_pool!(), session_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        if session.status != Some(UploadStatus::Uploading as i8) {
+            return Err(AppError::UploadSessionNotExists);
+        }
+        let parts = storage!()
+            .list_parts(
+                config!().minio.file_bucket.as_str(),
+                session
+                    .path
+                    .ok_or(AppError::UploadSessionNotExists)?
+                    .as_str(),
+                session
+                    .upload_id
+                    .ok_or(AppError::UploadSessionNotExists)?
+                    .as_str(),
+            )
+            .await?;
+        Ok(parts
+            .into_iter()
+            .map(|part| UploadedPartVo {
+                part: part.part,
+                etag: part.etag,
+                size: part.size,
+            })
+            .collect())
+    }
+
+    // Presigned urls for up to count parts from start on, never past the planned part count.
+    // Checksummed uploads get one url per supplied checksum, checksums[i] being part start + i
+    pub async fn get_part_upload_urls(
+        session: &UploadSession,
+        start: i64,
+        count: i64,
+        checksums: &[String],
+    ) -> Result<Vec<(i64, String)>, AppError> {
+        let upload_id = session
+            .upload_id
+            .as_deref()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        let algorithm = session.checksum_algorithm();
+        let count = match algorithm {
+            Some(_) if checksums.is_empty() => {
+                return Err(AppError::MissingField("checksums".into()));
+            }
+            Some(_) => count.min(checksums.len() as i64),
+            None => count,
+        };
+        let path = session
+            .path
+            .as_deref()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        let owner = UploadOwner {
+            user_id: session.user_id.ok_or(AppError::UploadSessionNotExists)?,
+            session_id: session.id.ok_or(AppError::UploadSessionNotExists)?,
+        };
+        let total = match session.part_size {
+            Some(part_size) => part_count(session.size.unwrap_or_default(), part_size),
+            None => MAX_PARTS,
+        };
+        if start < 1 || start > total {
+            return Err(AppError::UploadPlanMismatch);
+        }
+        let end = (start + count.clamp(1, config!().upload.url_window as i64) - 1).min(total);
+        let mut urls = Vec::with_capacity((end - start + 1) as usize);
+        for (index, part) in (start..=end).enumerate() {
+            let checksum = match algorithm {
+                Some(algorithm) if algorithm.is_valid(&checksums[index]) => Some(PartChecksum {
+                    algorithm,
+                    value: checksums[index].clone(),
+                }),
+                Some(_) => return Err(AppError::MissingField("checksums".into())),
+                None => None,
+            };
+            let url = storage!()
+                .part_upload_url(
+                    config!().minio.file_bucket.as_str(),
+                    path,
+                    upload_id,
+                    part,
+                    checksum.as_ref(),
+                    &owner,
+                )
+                .await?;
+            urls.push((part, url));
+        }
+        Ok(urls)
+    }
+
+    // Completing must give exactly the planned object, every part in order at its planned size
+    // and, for checksummed uploads, with the checksum the client announced for it
+    async fn check_upload_plan(
+        session: &UploadSession,
+        parts: &[UploadedPart],
+    ) -> Result<(), AppError> {
+        let (Some(size), Some(part_size)) = (session.size, session.part_size) else {
+            return Ok(());
+        };
+        if parts.len() as i64 != part_count(size, part_size) {
+            return Err(AppError::UploadPlanMismatch);
+        }
+        let stored: HashMap<i64, StoredPart> = storage!()
+            .list_parts(
+                config!().minio.file_bucket.as_str(),
+                session
+                    .path
+                    .as_deref()
+                    .ok_or(AppError::UploadSessionNotExists)?,
+                session
+                    .upload_id
+                    .as_deref()
+                    .ok_or(AppError::UploadSessionNotExists)?,
+            )
+            .await?
+            .into_iter()
+            .map(|part| (part.part, part))
+            .collect();
+        for (index, part) in parts.iter().enumerate() {
+            let Some(stored) = stored.get(&part.part) else {
+                return Err(AppError::UploadPlanMismatch);
+            };
+            if part.part != index as i64 + 1
+                || stored.size != Some(expected_part_size(size, part_size, part.part))
+                || stored.etag.as_deref().map(|e| e.trim_matches('"'))
+                    != Some(part.etag.trim_matches('"'))
+            {
+                return Err(AppError::UploadPlanMismatch);
+            }
+            if let Some(algorithm) = session.checksum_algorithm() {
+                match &part.checksum {
+                    Some(checksum)
+                        if stored.checksum(algorithm) == Some(checksum.value.as_str()) => {}
+                    _ => return Err(AppError::ChecksumMismatch),
                 }
-                Err(e) => {
-                    error!("{} upload fail, user: {}, E: {}", server_path, user_id, e)
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn reap_expired_uploads(ttl_min: i64) -> Result<usize, AppError> {
+        let sessions =
+            UploadSession::select_expired(db_pool!(), UploadStatus::Uploading as i8, ttl_min)
+                .await?;
+        let mut count = 0;
+        for session in sessions {
+            let session_id = session.id.ok_or(AppError::UploadSessionNotExists)?;
+            match Self::abort_upload_session(&session).await {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => error!("abort upload session {} fail, E: {}", session_id, e),
+            }
+        }
+        Ok(count)
+    }
+
+    pub async fn abort_upload_session(session: &UploadSession) -> Result<bool, AppError> {
+        let session_id = session.id.ok_or(AppError::UploadSessionNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        // Only the caller that moves the session out of uploading owns the cleanup
+        if !UploadSession::update_status_by_id_status(
+            &tx,
+            &session_id,
+            UploadStatus::Uploading,
+            UploadStatus::Aborted,
+        )
+        .await?
+        {
+            return Ok(false);
+        }
+        File::delete_by_id(&tx, &session.file_id.ok_or(AppError::FileNotExists)?).await?;
+        User::update_total_size_by_id(
+            &tx,
+            &session.user_id.ok_or(AppError::UserNotExists)?,
+            &-session.size.unwrap_or_default(),
+        )
+        .await?;
+        tx.commit().await?;
+        if let Err(e) = storage!()
+            .abort_multipart(
+                config!().minio.file_bucket.as_str(),
+                session
+                    .path
+                    .as_ref()
+                    .ok_or(AppError::UploadSessionNotExists)?,
+                session
+                    .upload_id
+                    .as_ref()
+                    .ok_or(AppError::UploadSessionNotExists)?,
+            )
+            .await
+        {
+            // The multipart upload may already be gone on the storage side
+            error!("abort multipart upload {} fail, E: {}", session_id, e);
+        }
+        Ok(true)
+    }
+
+    pub async fn abort_upload(user_id: &Uuid, session_id: &Uuid) -> Result<(), AppError> {
+        let session = UploadSession::select_by_id_userid(db_pool!(), session_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        if !Self::abort_upload_session(&session).await? {
+            return Err(AppError::UploadSessionNotExists);
+        }
+        Ok(())
+    }
+
+    pub async fn collect_unreferenced_files(grace_min: i64, limit: i64) -> Result<usize, AppError> {
+        let files = File::select_unreferenced(db_pool!(), grace_min, limit).await?;
+        let mut count = 0;
+        for file in files {
+            let file_id = file.id.ok_or(AppError::FileNotExists)?;
+            // Drop the row first, so a failed object delete leaks storage instead of a dangling row.
+            // An attach holding the row makes it skip this round
+            let tx = begin_tx(db_pool!()).await?;
+            if !File::lock_for_delete(&tx, &file_id).await?
+                || !File::remove_unreferenced_by_id(&tx, &file_id).await?
+            {
+                continue;
+            }
+            tx.commit().await?;
+            if let Some(thumbnail) = file.thumbnail {
+                for size in &config!().thumbnail.sizes {
+                    let key = thumbnail_key(thumbnail.as_str(), *size);
+                    if let Err(e) = storage!()
+                        .delete(config!().minio.thumbnail_bucket.as_str(), key.as_str())
+                        .await
+                    {
+                        error!("delete thumbnail {} fail, E: {}", key, e);
+                    }
                 }
             }
+            let Some(path) = file.path else {
+                count += 1;
+                continue;
+            };
+            match storage!()
+                .delete(config!().minio.file_bucket.as_str(), path.as_str())
+                .await
+            {
+                Ok(()) => count += 1,
+                Err(e) => error!("delete object {} fail, E: {}", path, e),
+            }
+        }
+        Ok(count)
+    }
+
+    // Only the finish that moves the session out of uploading attaches the file, a replayed or
+    // concurrent one fails on the status flip
+    pub async fn set_completed_upload(
+        session: UploadSession,
+        target: UploadTarget,
+        parts: Vec<(i64, String, Option<String>)>,
+    ) -> Result<(), AppError> {
+        let session_id = session.id.ok_or(AppError::UploadSessionNotExists)?;
+        let user_id = session.user_id.ok_or(AppError::UserNotExists)?;
+        let server_path = session
+            .path
+            .clone()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        let upload_id = session
+            .upload_id
+            .clone()
+            .ok_or(AppError::UploadSessionNotExists)?;
+        let algorithm = session.checksum_algorithm();
+        let parts: Vec<UploadedPart> = parts
+            .into_iter()
+            .map(|(part, etag, checksum)| UploadedPart {
+                part,
+                etag,
+                checksum: algorithm
+                    .zip(checksum)
+                    .map(|(algorithm, value)| PartChecksum { algorithm, value }),
+            })
+            .collect();
+        Self::check_upload_plan(&session, &parts).await?;
+        Self::check_target(&user_id, &target).await?;
+        storage!()
+            .complete_multipart(
+                config!().minio.file_bucket.as_str(),
+                server_path.as_str(),
+                upload_id.as_str(),
+                parts,
+            )
+            .await?;
+        let file_id = session.file_id.ok_or(AppError::FileNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        if !UploadSession::update_status_by_id_status(
+            &tx,
+            &session_id,
+            UploadStatus::Uploading,
+            UploadStatus::Hashing,
+        )
+        .await?
+        {
+            return Err(AppError::UploadSessionNotExists);
+        }
+        let versioned = Self::attach_file(&tx, user_id, file_id, &target).await?;
+        tx.commit().await?;
+        Self::after_attach(&user_id, versioned).await;
+        // Session stays hashing until after_upload lands, the retry job picks up anything lost
+        tokio::spawn(async move {
+            match Self::after_upload(&session).await {
+                Ok(_) => info!("{} upload finish, user: {}", server_path, user_id),
+                Err(e) => error!("{} upload fail, user: {}, E: {}", server_path, user_id, e),
+            }
         });
         Ok(())
     }
 
+    // Overwriting an existing folder keeps it as is. Returns the folder's id either way
     pub async fn mkdir_item(
         user_id: Uuid,
         logic_name: String,
         parent_id: Option<Uuid>,
-    ) -> Result<(), AppError> {
-        let item = Item::new(user_id, None, parent_id, true, logic_name, true);
-        Item::insert(db_pool!(), &item).await?;
-        Ok(())
+        conflict: ConflictPolicy,
+    ) -> Result<Uuid, AppError> {
+        let tx = begin_tx(db_pool!()).await?;
+        Item::lock_folder(&tx, &user_id, parent_id).await?;
+        Item::check_parent(&tx, &user_id, parent_id).await?;
+        let folder_id = match Item::resolve_name(
+            &tx,
+            &user_id,
+            parent_id,
+            &logic_name,
+            conflict,
+            None,
+        )
+        .await?
+        {
+            NameSlot::Free(logic_name) => {
+                let item = Item::new(user_id, None, parent_id, true, logic_name, true);
+                Item::insert(&tx, &item).await?;
+                item.id
+            }
+            NameSlot::Taken(existing) if existing.is_folder == Some(true) => existing.id,
+            NameSlot::Taken(_) => return Err(AppError::ItemNameConflict),
+        };
+        tx.commit().await?;
+        folder_id.ok_or(AppError::ItemNotExists)
+    }
+
+    // mkdir -p, every missing folder on the way is created and existing ones are kept
+    pub async fn mkdir_path(user_id: Uuid, path: &str) -> Result<Option<Uuid>, AppError> {
+        let mut parent_id = None;
+        for segment in path_segments(path)? {
+            parent_id = Some(
+                Self::mkdir_item(user_id, segment, parent_id, ConflictPolicy::Overwrite).await?,
+            );
+        }
+        Ok(parent_id)
+    }
+
+    pub async fn resolve_path(user_id: &Uuid, path: &str) -> Result<Option<Item>, AppError> {
+        Item::resolve_path(db_pool!(), user_id, &path_segments(path)?).await
+    }
+
+    pub async fn resolve_folder_path(user_id: &Uuid, path: &str) -> Result<Option<Uuid>, AppError> {
+        Item::resolve_folder_path(db_pool!(), user_id, path).await
+    }
+
+    pub async fn get_breadcrumbs(
+        user_id: &Uuid,
+        item_id: &Uuid,
+    ) -> Result<Vec<Breadcrumb>, AppError> {
+        let crumbs = Item::select_ancestors_by_id(db_pool!(), item_id, user_id).await?;
+        if crumbs.is_empty() {
+            return Err(AppError::ItemNotExists);
+        }
+        Ok(crumbs)
     }
 
     pub async fn rename_item(
         user_id: &Uuid,
         item_id: &Uuid,
-        logic_name: &String,
-    ) -> Result<File, AppError> {
-        let file = File::new();
-        Item::update_logic_name_by_id(db_pool!(), item_id, user_id, logic_name).await?;
-        Ok(file)
+        logic_name: &str,
+        conflict: ConflictPolicy,
+    ) -> Result<(), AppError> {
+        let item = Self::get_item_by_id(user_id, item_id).await?;
+        let parent_id = item.parent_id;
+        Self::place_item(user_id, item, parent_id, logic_name, conflict).await
+    }
+
+    // Put an existing item under parent_id with logic_name, resolving a clash per the policy
+    async fn place_item(
+        user_id: &Uuid,
+        item: Item,
+        parent_id: Option<Uuid>,
+        logic_name: &str,
+        conflict: ConflictPolicy,
+    ) -> Result<(), AppError> {
+        let item_id = item.id.ok_or(AppError::ItemNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        if parent_id != item.parent_id {
+            Item::lock_moves(&tx, user_id).await?;
+            Item::check_parent(&tx, user_id, parent_id).await?;
+            if let Some(parent_id) = parent_id
+                && Item::tree_contains(&tx, &item_id, user_id, &parent_id).await?
+            {
+                return Err(AppError::InvalidParent);
+            }
+        }
+        Item::lock_folder(&tx, user_id, parent_id).await?;
+        let slot = Item::resolve_name(
+            &tx,
+            user_id,
+            parent_id,
+            logic_name,
+            conflict,
+            Some(&item_id),
+        )
+        .await?;
+        let versioned = match slot {
+            NameSlot::Free(logic_name) => {
+                Item::update_place_by_id(&tx, &item_id, user_id, parent_id, &logic_name).await?;
+                None
+            }
+            NameSlot::Taken(existing) => {
+                Some(Self::overwrite_with(&tx, user_id, &item, &existing).await?)
+            }
+        };
+        tx.commit().await?;
+        Self::after_attach(user_id, versioned).await;
+        Ok(())
     }
 
     pub async fn get_download_url(file_id: &Uuid, file_name: &String) -> Result<String, AppError> {
-        let file_path = File::select_by_id(db_pool!(), file_id)
+        let file = File::select_by_id(db_pool!(), file_id)
             .await?
-            .get(0)
-            .ok_or(AppError::FileNotExists)?
-            .path
-            .clone()
+            .into_iter()
+            .next()
             .ok_or(AppError::FileNotExists)?;
-        let url = generate_download_url(
-            minio_client!(),
-            config!().minio.file_bucket.as_str(),
-            file_path.as_str(),
-            file_name,
+        let file_path = file.path.ok_or(AppError::FileNotExists)?;
+        let url = storage!()
+            .download_url(
+                config!().minio.file_bucket.as_str(),
+                file_path.as_str(),
+                file_name,
+                file.file_type.as_deref(),
+            )
+            .await?;
+        Ok(url)
+    }
+
+    // The item's file plus what the backend reports for its object
+    pub async fn get_owned_file(
+        user_id: &Uuid,
+        item_id: &Uuid,
+    ) -> Result<(Item, File, ObjectHead), AppError> {
+        let item = Item::select_by_id_userid(db_pool!(), item_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::ItemNotExists)?;
+        let file_id = item.file_id.ok_or(AppError::FileNotExists)?;
+        let file = File::select_by_id(db_pool!(), &file_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::FileNotExists)?;
+        let head = storage!()
+            .head(
+                config!().minio.file_bucket.as_str(),
+                file.path.as_deref().ok_or(AppError::FileNotExists)?,
+            )
+            .await?;
+        Ok((item, file, head))
+    }
+
+    pub async fn read_file(file: &File, range: Option<(i64, i64)>) -> Result<ByteStream, AppError> {
+        let bucket = config!().minio.file_bucket.as_str();
+        let path = file.path.as_deref().ok_or(AppError::FileNotExists)?;
+        match range {
+            Some((start, end)) => storage!().get_stream_range(bucket, path, start, end).await,
+            None => storage!().get(bucket, path).await,
+        }
+    }
+
+    pub async fn collect_archive_entries(
+        user_id: &Uuid,
+        item_ids: &[Uuid],
+    ) -> Result<Vec<ArchiveEntry>, AppError> {
+        let mut queue = VecDeque::new();
+        for item_id in item_ids {
+            let item = Item::select_by_id_userid(db_pool!(), item_id, user_id)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(AppError::ItemNotExists)?;
+            queue.push_back((String::new(), item));
+        }
+        let mut entries = Vec::new();
+        let mut used = HashSet::new();
+        let mut visited = HashSet::new();
+        while let Some((prefix, item)) = queue.pop_front() {
+            let item_id = item.id.ok_or(AppError::ItemNotExists)?;
+            if !visited.insert(item_id) {
+                continue;
+            }
+            let name = archive_entry_name(
+                &mut used,
+                &prefix,
+                item.logic_name.as_deref().unwrap_or_default(),
+            );
+            if item.is_folder == Some(true) {
+                let path = format!("{}/", name);
+                for child in Item::select_by_parent_userid(db_pool!(), &item_id, user_id).await? {
+                    queue.push_back((path.clone(), child));
+                }
+                entries.push(ArchiveEntry { path, object: None });
+            } else {
+                // Resolved here so a missing file fails the request before any byte is sent
+                let file_id = item.file_id.ok_or(AppError::FileNotExists)?;
+                let file = File::select_by_id(db_pool!(), &file_id)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or(AppError::FileNotExists)?;
+                entries.push(ArchiveEntry {
+                    path: name,
+                    object: Some(file.path.ok_or(AppError::FileNotExists)?),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    // Objects are copied chunk by chunk into stored entries, nothing is buffered whole
+    pub async fn write_archive<W>(entries: Vec<ArchiveEntry>, writer: W) -> Result<(), AppError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let bucket = config!().minio.file_bucket.as_str();
+        let mut zip = ZipFileWriter::with_tokio(writer);
+        for entry in entries {
+            let builder = ZipEntryBuilder::new(entry.path.into(), Compression::Stored);
+            let Some(object) = entry.object else {
+                zip.write_entry_whole(builder, &[])
+                    .await
+                    .map_err(|e| AppError::InnerError(e.to_string()))?;
+                continue;
+            };
+            let mut body = storage!().get(bucket, object.as_str()).await?;
+            let mut entry_writer = zip
+                .write_entry_stream(builder)
+                .await
+                .map_err(|e| AppError::InnerError(e.to_string()))?;
+            while let Some(chunk) = body
+                .try_next()
+                .await
+                .map_err(|e| AppError::StorageError(e.to_string()))?
+            {
+                entry_writer.write_all(&chunk).await?;
+            }
+            entry_writer
+                .close()
+                .await
+                .map_err(|e| AppError::InnerError(e.to_string()))?;
+        }
+        zip.close()
+            .await
+            .map_err(|e| AppError::InnerError(e.to_string()))?;
+        Ok(())
+    }
+
+    // Runs in the caller's transaction, the file's size is already charged by then. Returns the
+    // item that got a new version, if any
+    async fn attach_file(
+        tx: &dyn Executor,
+        user_id: Uuid,
+        file_id: Uuid,
+        target: &UploadTarget,
+    ) -> Result<Option<Uuid>, AppError> {
+        let (parent_id, logic_name, conflict) = match target {
+            UploadTarget::New {
+                parent_id,
+                logic_name,
+                conflict,
+            } => (*parent_id, logic_name, *conflict),
+            UploadTarget::Version(item_id) => {
+                ItemVersion::push(tx, &user_id, item_id, &file_id).await?;
+                return Ok(Some(*item_id));
+            }
+        };
+        Item::lock_folder(tx, &user_id, parent_id).await?;
+        Item::check_parent(tx, &user_id, parent_id).await?;
+        match Item::resolve_name(tx, &user_id, parent_id, logic_name, conflict, None).await? {
+            NameSlot::Free(logic_name) => {
+                let item = Item::new(user_id, Some(file_id), parent_id, false, logic_name, true);
+                Item::insert(tx, &item).await?;
+                Ok(None)
+            }
+            NameSlot::Taken(existing) => {
+                let item_id = Self::overwritable(&existing)?;
+                ItemVersion::push(tx, &user_id, &item_id, &file_id).await?;
+                Ok(Some(item_id))
+            }
+        }
+    }
+
+    // Only a file can take another file as its next version
+    fn overwritable(existing: &Item) -> Result<Uuid, AppError> {
+        match existing.is_folder {
+            Some(false) => existing.id.ok_or(AppError::ItemNotExists),
+            _ => Err(AppError::ItemNameConflict),
+        }
+    }
+
+    // Fail before the upload is completed rather than after, the real check runs under the lock
+    async fn check_target(user_id: &Uuid, target: &UploadTarget) -> Result<(), AppError> {
+        let UploadTarget::New {
+            parent_id,
+            logic_name,
+            conflict,
+        } = target
+        else {
+            return Ok(());
+        };
+        Item::check_parent(db_pool!(), user_id, *parent_id).await?;
+        if let NameSlot::Taken(existing) =
+            Item::resolve_name(db_pool!(), user_id, *parent_id, logic_name, *conflict, None).await?
+        {
+            Self::overwritable(&existing)?;
+        }
+        Ok(())
+    }
+
+    // Pruning is housekeeping, it must not fail an upload that already landed
+    async fn after_attach(user_id: &Uuid, versioned: Option<Uuid>) {
+        let Some(item_id) = versioned else {
+            return;
+        };
+        if let Err(e) = ItemVersion::prune(db_pool!(), user_id, &item_id).await {
+            error!("prune versions of {} fail, E: {}", item_id, e);
+        }
+    }
+
+    // Replace an existing file with the one being moved or renamed onto it. The moved file becomes
+    // its next version and the moved item goes away, its own history with it
+    async fn overwrite_with(
+        tx: &dyn Executor,
+        user_id: &Uuid,
+        item: &Item,
+        existing: &Item,
+    ) -> Result<Uuid, AppError> {
+        let item_id = item.id.ok_or(AppError::ItemNotExists)?;
+        let file_id = match item.is_folder {
+            Some(false) => item.file_id.ok_or(AppError::FileNotExists)?,
+            _ => return Err(AppError::ItemNameConflict),
+        };
+        let existing_id = Self::overwritable(existing)?;
+        ItemVersion::push(tx, user_id, &existing_id, &file_id).await?;
+        let history = ItemVersion::sum_size_by_item(tx, &item_id, user_id).await?;
+        ItemVersion::delete_by_item(tx, &item_id, user_id).await?;
+        Item::delete_by_id(tx, &item_id, user_id).await?;
+        User::update_total_size_by_id(tx, user_id, &-history).await?;
+        Ok(existing_id)
+    }
+
+    pub async fn get_versions(
+        user_id: &Uuid,
+        item_id: &Uuid,
+    ) -> Result<Vec<ItemVersionVo>, AppError> {
+        Self::get_item_by_id(user_id, item_id).await?;
+        ItemVersion::select_vo_by_item(db_pool!(), item_id, user_id).await
+    }
+
+    pub async fn get_version_download_url(
+        user_id: &Uuid,
+        item_id: &Uuid,
+        version_id: &Uuid,
+    ) -> Result<String, AppError> {
+        let item = Self::get_item_by_id(user_id, item_id).await?;
+        let file_id =
+            ItemVersion::select_by_id_item_userid(db_pool!(), version_id, item_id, user_id)
+                .await?
+                .into_iter()
+                .next()
+                .and_then(|version| version.file_id)
+                .ok_or(AppError::FileNotExists)?;
+        Self::get_download_url(&file_id, &item.logic_name.unwrap_or_default()).await
+    }
+
+    // Swap the version with the current file, both stay charged so the quota does not move
+    pub async fn restore_version(
+        user_id: &Uuid,
+        item_id: &Uuid,
+        version_id: &Uuid,
+    ) -> Result<(), AppError> {
+        let item = Self::get_item_by_id(user_id, item_id).await?;
+        let current = item.file_id.ok_or(AppError::FileNotExists)?;
+        let version =
+            ItemVersion::select_by_id_item_userid(db_pool!(), version_id, item_id, user_id)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(AppError::FileNotExists)?;
+        let version_file = version.file_id.ok_or(AppError::FileNotExists)?;
+        let current_size = File::select_by_id(db_pool!(), &current)
+            .await?
+            .first()
+            .and_then(|file| file.size)
+            .unwrap_or_default();
+        let tx = begin_tx(db_pool!()).await?;
+        if !ItemVersion::delete_by_id(&tx, version_id).await? {
+            return Err(AppError::FileNotExists);
+        }
+        ItemVersion::insert(
+            &tx,
+            &ItemVersion::new(*user_id, *item_id, current, current_size),
         )
         .await?;
-        Ok(url)
+        if !Item::update_file_by_id(&tx, item_id, user_id, &current, &version_file).await? {
+            return Err(AppError::ItemNotExists);
+        }
+        tx.commit().await?;
+        Ok(())
     }
 
     pub async fn get_item_list(
@@ -155,93 +1120,542 @@ impl FileService {
         parent_id: Option<Uuid>,
         page_no: u64,
         page_size: u64,
-    ) -> Result<Page<Item>, AppError> {
-        let data = match parent_id {
-            Some(parent_id) => {
-                Item::select_page_by_parent(
-                    db_pool!(),
-                    &PageRequest::new(page_no, page_size),
-                    &parent_id,
-                    &user_id,
-                )
+        order: ItemOrder,
+    ) -> Result<Page<ItemVo>, AppError> {
+        let mut data = Item::select_vo_page_by_parent(
+            db_pool!(),
+            &PageRequest::new(page_no, page_size),
+            parent_id,
+            &user_id,
+            order,
+        )
+        .await?;
+        Self::fill_folder_stats(&user_id, &mut data.records).await?;
+        Ok(data)
+    }
+
+    // Keyset mode for large folders, the cursor comes back from the previous page
+    pub async fn get_item_cursor(
+        user_id: Uuid,
+        parent_id: Option<Uuid>,
+        cursor: &str,
+        limit: u64,
+        order: ItemOrder,
+    ) -> Result<CursorPage<ItemVo>, AppError> {
+        let mut data =
+            Item::select_vo_after_by_parent(db_pool!(), cursor, limit, parent_id, &user_id, order)
+                .await?;
+        Self::fill_folder_stats(&user_id, &mut data.records).await?;
+        Ok(data)
+    }
+
+    // Folder rows carry their recursive size and counts, after any cursor was taken
+    async fn fill_folder_stats(user_id: &Uuid, records: &mut [ItemVo]) -> Result<(), AppError> {
+        let folder_ids: Vec<Uuid> = records
+            .iter()
+            .filter(|item| item.is_folder == Some(true))
+            .filter_map(|item| item.id)
+            .collect();
+        let stats = Self::select_folder_stats(user_id, &folder_ids).await?;
+        for item in records.iter_mut() {
+            if let Some(stats) = item.id.and_then(|id| stats.get(&id)) {
+                item.size = Some(stats.size);
+                item.file_count = Some(stats.file_count);
+                item.folder_count = Some(stats.folder_count);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn search_items(
+        user_id: &Uuid,
+        search: &ItemSearch,
+        page_no: u64,
+        page_size: u64,
+    ) -> Result<Page<ItemVo>, AppError> {
+        if let Some(root_id) = search.root_id {
+            Item::check_parent(db_pool!(), user_id, Some(root_id)).await?;
+        }
+        Item::search_vo_page(
+            db_pool!(),
+            &PageRequest::new(page_no, page_size),
+            user_id,
+            search,
+        )
+        .await
+    }
+
+    pub async fn get_folder_stats(user_id: &Uuid, item_id: &Uuid) -> Result<FolderStats, AppError> {
+        Self::select_folder_stats(user_id, &[*item_id])
+            .await?
+            .remove(item_id)
+            .ok_or(AppError::ItemNotExists)
+    }
+
+    // Only trees changed since the cached stamp are walked again. The stamp is read first so a
+    // write racing the walk leaves the entry stale rather than wrong
+    async fn select_folder_stats(
+        user_id: &Uuid,
+        ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, FolderStats>, AppError> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let stamp = User::select_tree_stamp(db_pool!(), user_id).await?;
+        let mut stats = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = FOLDER_STATS.lock().unwrap();
+            for id in ids {
+                match cache.get(&(*user_id, *id)) {
+                    Some((cached, folder)) if *cached == stamp => {
+                        stats.insert(*id, folder.clone());
+                    }
+                    _ => missing.push(*id),
+                }
+            }
+        }
+        let fresh = Item::select_stats_by_ids(db_pool!(), &missing, user_id).await?;
+        let mut cache = FOLDER_STATS.lock().unwrap();
+        if cache.len() + fresh.len() > FOLDER_STATS_CAP {
+            cache.clear();
+        }
+        for folder in fresh {
+            if let Some(id) = folder.id {
+                cache.insert((*user_id, id), (stamp, folder.clone()));
+                stats.insert(id, folder);
+            }
+        }
+        Ok(stats)
+    }
+
+    // New items share the source files, only the quota grows. The trees are walked in the
+    // background, the job reports how far it got
+    pub async fn copy_items(
+        user_id: Uuid,
+        item_ids: &[Uuid],
+        parent_id: Option<Uuid>,
+        conflict: ConflictPolicy,
+    ) -> Result<CopyJob, AppError> {
+        Item::check_parent(db_pool!(), &user_id, parent_id).await?;
+        let mut roots = Vec::with_capacity(item_ids.len());
+        let mut total = TreeSize::default();
+        for item_id in item_ids {
+            let item = Self::get_item_by_id(&user_id, item_id).await?;
+            if let Some(parent_id) = parent_id
+                && Item::tree_contains(db_pool!(), item_id, &user_id, &parent_id).await?
+            {
+                return Err(AppError::InvalidParent);
+            }
+            let tree = Item::sum_tree_by_id(db_pool!(), item_id, &user_id).await?;
+            total.count += tree.count;
+            total.size += tree.size;
+            roots.push(item);
+        }
+        // Each copied file reserves its own size, this only refuses copies that cannot fit at all
+        let user = User::select_by_id(db_pool!(), &user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::UserNotExists)?;
+        if user.total_size.unwrap_or_default() + total.size > user.max_size.unwrap_or_default() {
+            return Err(AppError::UserOutSize);
+        }
+
+        let job = CopyJob::new(user_id, parent_id, total.count);
+        let job_id = job.id.ok_or(AppError::InnerError("copy-job-id".into()))?;
+        CopyJob::insert(db_pool!(), &job).await?;
+        tokio::spawn(async move {
+            let mut copied = 0;
+            let result =
+                Self::copy_trees(&job_id, &user_id, roots, parent_id, conflict, &mut copied).await;
+            let (status, message) = match result {
+                Ok(()) => (CopyStatus::Completed, None),
+                Err(e) => {
+                    error!("copy job {} fail, E: {}", job_id, e);
+                    (CopyStatus::Failed, Some(e.to_string()))
+                }
+            };
+            if let Err(e) =
+                CopyJob::finish_by_id(db_pool!(), &job_id, status, copied, message).await
+            {
+                error!("finish copy job {} fail, E: {}", job_id, e);
+            }
+        });
+        Ok(job)
+    }
+
+    // Jobs run inside the process that accepted them, a restart leaves them running forever
+    pub async fn fail_stale_copies(stale_min: i64) -> Result<u64, AppError> {
+        CopyJob::fail_stale(db_pool!(), stale_min).await
+    }
+
+    pub async fn get_copy_job(user_id: &Uuid, job_id: &Uuid) -> Result<CopyJob, AppError> {
+        CopyJob::select_by_id_userid(db_pool!(), job_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::ItemNotExists)
+    }
+
+    // Breadth first, a folder is copied before anything inside it
+    async fn copy_trees(
+        job_id: &Uuid,
+        user_id: &Uuid,
+        roots: Vec<Item>,
+        parent_id: Option<Uuid>,
+        conflict: ConflictPolicy,
+        copied: &mut i64,
+    ) -> Result<(), AppError> {
+        let mut queue: VecDeque<(Item, Option<Uuid>)> =
+            roots.into_iter().map(|item| (item, parent_id)).collect();
+        // A parent loop left by older moves would otherwise copy forever
+        let mut visited = HashSet::new();
+        while let Some((item, parent_id)) = queue.pop_front() {
+            if !item.id.is_some_and(|id| visited.insert(id)) {
+                continue;
+            }
+            let copy_id = Self::copy_item(user_id, &item, parent_id, conflict).await?;
+            *copied += 1;
+            if *copied % COPY_PROGRESS_STEP == 0 {
+                CopyJob::update_copied_by_id(db_pool!(), job_id, *copied).await?;
+            }
+            if item.is_folder == Some(true) {
+                let item_id = item.id.ok_or(AppError::ItemNotExists)?;
+                for child in Item::select_by_parent_userid(db_pool!(), &item_id, user_id).await? {
+                    queue.push_back((child, Some(copy_id)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the item that now holds the copy. Overwriting merges a folder into the existing one
+    // and makes a file the next version of the existing file
+    async fn copy_item(
+        user_id: &Uuid,
+        item: &Item,
+        parent_id: Option<Uuid>,
+        conflict: ConflictPolicy,
+    ) -> Result<Uuid, AppError> {
+        let is_folder = item.is_folder == Some(true);
+        let logic_name = item.logic_name.clone().unwrap_or_default();
+        let tx = begin_tx(db_pool!()).await?;
+        let size = match item.file_id {
+            Some(file_id) if !is_folder => File::select_for_share(&tx, &file_id)
                 .await?
+                .size
+                .unwrap_or_default(),
+            _ => 0,
+        };
+        Item::lock_folder(&tx, user_id, parent_id).await?;
+        let slot = Item::resolve_name(&tx, user_id, parent_id, &logic_name, conflict, None).await?;
+        let (copy_id, versioned) = match slot {
+            NameSlot::Free(logic_name) => {
+                User::reserve_total_size_by_id(&tx, user_id, &size).await?;
+                let copy = Item::new(
+                    *user_id,
+                    item.file_id,
+                    parent_id,
+                    is_folder,
+                    logic_name,
+                    item.uploaded.unwrap_or(true),
+                );
+                Item::insert(&tx, &copy).await?;
+                (copy.id.ok_or(AppError::ItemNotExists)?, None)
             }
-            None => {
-                Item::select_page_root(db_pool!(), &PageRequest::new(page_no, page_size), &user_id)
-                    .await?
+            // Copying onto itself changes nothing
+            NameSlot::Taken(existing) if existing.id == item.id => {
+                (existing.id.ok_or(AppError::ItemNotExists)?, None)
+            }
+            NameSlot::Taken(existing) if is_folder => match existing.is_folder {
+                Some(true) => (existing.id.ok_or(AppError::ItemNotExists)?, None),
+                _ => return Err(AppError::ItemNameConflict),
+            },
+            NameSlot::Taken(existing) => {
+                let existing_id = Self::overwritable(&existing)?;
+                let file_id = item.file_id.ok_or(AppError::FileNotExists)?;
+                User::reserve_total_size_by_id(&tx, user_id, &size).await?;
+                ItemVersion::push(&tx, user_id, &existing_id, &file_id).await?;
+                (existing_id, Some(existing_id))
             }
         };
-        Ok(data)
+        tx.commit().await?;
+        Self::after_attach(user_id, versioned).await;
+        Ok(copy_id)
     }
 
+    // Moves the whole subtree to the trash, the quota it holds is released on purge
     pub async fn delete_item(user_id: &Uuid, item_id: &Uuid) -> Result<(), AppError> {
-        let item_vec = Item::select_by_id_userid(db_pool!(), item_id, user_id).await?;
-        let item = item_vec.get(0).ok_or(AppError::FileNotExists)?;
-        if item.is_folder.ok_or(AppError::FileNotExists)? {
-            Item::delete_sub_by_id(db_pool!(), item_id, user_id).await?;
-            Item::delete_by_id(db_pool!(), item_id, user_id).await?;
+        let tx = begin_tx(db_pool!()).await?;
+        let size = Item::sum_size_with_tree_by_id(&tx, item_id, user_id).await?;
+        let trash = Trash::new(*user_id, *item_id, size);
+        let trash_id = trash.id.ok_or(AppError::ItemNotExists)?;
+        if !Item::trash_tree_by_id(&tx, item_id, user_id, &trash_id).await? {
+            return Err(AppError::ItemNotExists);
         }
-        Item::delete_by_id(db_pool!(), item_id, user_id).await?;
+        Trash::insert(&tx, &trash).await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn get_item_to_file_id(user_id: &Uuid, item_id: &Uuid) -> Result<Uuid, AppError> {
-        let item_vec = Item::select_by_id_userid(db_pool!(), &item_id, &user_id).await?;
-        let item = item_vec.get(0).ok_or(AppError::FileNotExists)?;
-        item.file_id.ok_or(AppError::FileNotExists)
+    pub async fn get_trash(user_id: &Uuid) -> Result<Vec<TrashVo>, AppError> {
+        Trash::select_vo_by_userid(db_pool!(), user_id).await
     }
 
-    pub async fn get_item_by_id(user_id: &Uuid, item_id: &Uuid) -> Result<Item, AppError> {
-        let item_vec = Item::select_by_id_userid(db_pool!(), &user_id, &item_id).await?;
-        let item = item_vec.get(0).ok_or(AppError::FileNotExists)?.clone();
-        Ok(item)
+    // Back under the original parent, or the root once that is gone. A clashing name gets a number
+    pub async fn restore_trash(user_id: &Uuid, trash_id: &Uuid) -> Result<(), AppError> {
+        let trash = Self::get_trash_by_id(user_id, trash_id).await?;
+        let item_id = trash.item_id.ok_or(AppError::ItemNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        if !Trash::delete_by_id(&tx, trash_id).await? {
+            return Err(AppError::ItemNotExists);
+        }
+        let item = Item::select_trashed_by_id(&tx, &item_id, user_id, trash_id)
+            .await?
+            .ok_or(AppError::ItemNotExists)?;
+        let parent_id = match item.parent_id {
+            Some(parent_id) => Item::select_by_id_userid(&tx, &parent_id, user_id)
+                .await?
+                .into_iter()
+                .next()
+                .filter(|parent| parent.is_folder == Some(true))
+                .map(|_| parent_id),
+            None => None,
+        };
+        Item::lock_folder(&tx, user_id, parent_id).await?;
+        let slot = Item::resolve_name(
+            &tx,
+            user_id,
+            parent_id,
+            &item.logic_name.unwrap_or_default(),
+            ConflictPolicy::Rename,
+            None,
+        )
+        .await?;
+        let NameSlot::Free(logic_name) = slot else {
+            return Err(AppError::ItemNameConflict);
+        };
+        // The root takes its place as the tree comes back, it never holds a taken name
+        Item::restore_by_trash(&tx, trash_id, user_id, &item_id, parent_id, &logic_name).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn purge_trash(user_id: &Uuid, trash_id: &Uuid) -> Result<(), AppError> {
+        let trash = Self::get_trash_by_id(user_id, trash_id).await?;
+        if !Self::purge(&trash).await? {
+            return Err(AppError::ItemNotExists);
+        }
+        Ok(())
+    }
+
+    pub async fn empty_trash(user_id: &Uuid) -> Result<u64, AppError> {
+        let mut count = 0;
+        for trash in Trash::select_by_userid(db_pool!(), user_id).await? {
+            if Self::purge(&trash).await? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // Purge deletions older than the retention window, a batch at a time
+    pub async fn purge_expired_trash(retention_days: i64, limit: i64) -> Result<u64, AppError> {
+        let mut count = 0;
+        for trash in Trash::select_expired(db_pool!(), retention_days, limit).await? {
+            match Self::purge(&trash).await {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => error!("purge trash {:?} fail, E: {}", trash.id, e),
+            }
+        }
+        Ok(count)
+    }
+
+    async fn get_trash_by_id(user_id: &Uuid, trash_id: &Uuid) -> Result<Trash, AppError> {
+        Trash::select_by_id_userid(db_pool!(), trash_id, user_id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(AppError::ItemNotExists)
+    }
+
+    // Drop the versions, let go of the files and give the quota back. False when already gone
+    async fn purge(trash: &Trash) -> Result<bool, AppError> {
+        let trash_id = trash.id.ok_or(AppError::ItemNotExists)?;
+        let user_id = trash.user_id.ok_or(AppError::UserNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        if !Trash::delete_by_id(&tx, &trash_id).await? {
+            return Ok(false);
+        }
+        ItemVersion::delete_by_trash(&tx, &trash_id).await?;
+        Item::purge_by_trash(&tx, &trash_id).await?;
+        User::update_total_size_by_id(&tx, &user_id, &-trash.size.unwrap_or_default()).await?;
+        tx.commit().await?;
+        Ok(true)
     }
 
-    pub async fn create_new_file() -> Result<File, AppError> {
-        let file = File::new();
-        File::insert(db_pool!(), &file).await?;
-        Ok(file)
+    pub async fn get_item_by_id(user_id: &Uuid, item_id: &Uuid) -> Result<Item, AppError> {
+        let item_vec = Item::select_by_id_userid(db_pool!(), item_id, user_id).await?;
+        let item = item_vec.first().ok_or(AppError::ItemNotExists)?.clone();
+        Ok(item)
     }
 
     pub async fn move_item(
         user_id: &Uuid,
         item_id: &Uuid,
         parent_id: Option<Uuid>,
-    ) -> Result<File, AppError> {
-        let file = File::new();
-        Item::update_parent_by_id(db_pool!(), item_id, user_id, parent_id).await?;
-        Ok(file)
+        conflict: ConflictPolicy,
+    ) -> Result<(), AppError> {
+        let item = Self::get_item_by_id(user_id, item_id).await?;
+        let logic_name = item.logic_name.clone().unwrap_or_default();
+        Self::place_item(user_id, item, parent_id, &logic_name, conflict).await
     }
 
-    pub async fn after_upload(
-        user_id: Uuid,
-        file_id: Option<Uuid>,
-        parent_id: Option<Uuid>,
-        logic_name: String,
-        server_path: &String,
-    ) -> Result<Uuid, AppError> {
-        let item = Item::new(user_id, file_id, parent_id, false, logic_name, true);
-        Item::insert(db_pool!(), &item).await?;
-
-        // need to put in other thread
-        let download_url = generate_download_url(
-            minio_client!(),
-            config!().minio.file_bucket.as_str(),
-            server_path.as_str(),
-            file_id
-                .ok_or(AppError::InnerError("file-id".into()))?
-                .to_string()
-                .as_str(),
-        )
-        .await?;
-        let (size, hash) = get_size_and_hash(req_client!(), download_url.as_str()).await?;
-        File::update_size_sha_256(
-            db_pool!(),
-            &file_id.ok_or(AppError::InnerError("get-file-id error".into()))?,
-            size,
-            &hash,
+    pub async fn after_upload(session: &UploadSession) -> Result<bool, AppError> {
+        let session_id = session.id.ok_or(AppError::UploadSessionNotExists)?;
+        let file_id = session.file_id.ok_or(AppError::FileNotExists)?;
+        let path = session.path.clone().ok_or(AppError::FileNotExists)?;
+        let bucket = config!().minio.file_bucket.as_str();
+
+        // Prefer the checksum the backend already holds, stream the object only without one
+        let (size, hash) = match storage!().head(bucket, path.as_str()).await? {
+            ObjectHead {
+                size,
+                sha_256: Some(hash),
+            } => (size, hash),
+            ObjectHead { sha_256: None, .. } => {
+                get_size_and_hash(storage!().get(bucket, path.as_str()).await?).await?
+            }
+        };
+        let head = if size > 0 {
+            storage!()
+                .get_range(bucket, path.as_str(), 0, size.min(SNIFF_LEN) - 1)
+                .await?
+        } else {
+            Vec::new()
+        };
+        let file_type = sniff_mime(&head);
+        // A missing thumbnail must not hold the upload back
+        let thumbnail = Self::generate_thumbnails(path.as_str(), size, file_type.as_str())
+            .await
+            .unwrap_or_else(|e| {
+                error!("generate thumbnail {} fail, E: {}", path, e);
+                None
+            });
+        let tx = begin_tx(db_pool!()).await?;
+        if !UploadSession::update_status_by_id_status(
+            &tx,
+            &session_id,
+            UploadStatus::Hashing,
+            UploadStatus::Completed,
         )
-        .await?;
-        Ok(item.id.ok_or(AppError::FileNotExists)?)
+        .await?
+        {
+            return Ok(false);
+        }
+        File::update_size_sha_256(&tx, &file_id, size, &hash).await?;
+        File::update_file_type(&tx, &file_id, &file_type).await?;
+        if let Some(thumbnail) = thumbnail {
+            File::update_thumbnail(&tx, &file_id, &thumbnail).await?;
+        }
+        File::settle_size_by_id(&tx, &file_id, size - session.size.unwrap_or_default()).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Thumbnails share the object's key as prefix, one object per configured size
+    async fn generate_thumbnails(
+        path: &str,
+        size: i64,
+        file_type: &str,
+    ) -> Result<Option<String>, AppError> {
+        let bucket = config!().minio.file_bucket.as_str();
+        let limit = config!().thumbnail.max_source_mb * 1024 * 1024;
+        if !is_thumbnail_source(file_type) || size <= 0 || size as u64 > limit {
+            return Ok(None);
+        }
+        // The read stops at the limit too, whatever size was recorded
+        let mut stream = storage!().get(bucket, path).await?;
+        let mut data = Vec::with_capacity(size as usize);
+        while let Some(chunk) = stream
+            .try_next()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?
+        {
+            if (data.len() + chunk.len()) as u64 > limit {
+                return Ok(None);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let thumbnails = tokio::task::spawn_blocking(move || {
+            generate_thumbnails(&data, &config!().thumbnail.sizes)
+        })
+        .await
+        .map_err(|e| AppError::InnerError(e.to_string()))??;
+        for (thumbnail_size, thumbnail) in thumbnails {
+            storage!()
+                .put(
+                    config!().minio.thumbnail_bucket.as_str(),
+                    thumbnail_key(path, thumbnail_size).as_str(),
+                    ByteStream::from(thumbnail),
+                )
+                .await?;
+        }
+        Ok(Some(path.to_string()))
+    }
+
+    pub async fn get_thumbnail(
+        user_id: &Uuid,
+        item_id: &Uuid,
+        size: Option<u32>,
+    ) -> Result<(i64, ByteStream), AppError> {
+        let file_id = Item::select_by_id_userid(db_pool!(), item_id, user_id)
+            .await?
+            .first()
+            .ok_or(AppError::ItemNotExists)?
+            .file_id
+            .ok_or(AppError::FileNotExists)?;
+        let thumbnail = File::select_by_id(db_pool!(), &file_id)
+            .await?
+            .first()
+            .ok_or(AppError::FileNotExists)?
+            .thumbnail
+            .clone()
+            .ok_or(AppError::FileNotExists)?;
+        let size =
+            pick_thumbnail_size(&config!().thumbnail.sizes, size).ok_or(AppError::FileNotExists)?;
+        let bucket = config!().minio.thumbnail_bucket.as_str();
+        let key = thumbnail_key(thumbnail.as_str(), size);
+        let head = storage!().head(bucket, key.as_str()).await?;
+        Ok((head.size, storage!().get(bucket, key.as_str()).await?))
+    }
+
+    pub async fn retry_after_upload(stale_min: i64) -> Result<usize, AppError> {
+        let sessions =
+            UploadSession::select_stale(db_pool!(), UploadStatus::Hashing as i8, stale_min).await?;
+        let mut count = 0;
+        for session in sessions {
+            let Some(session_id) = session.id else {
+                continue;
+            };
+            if !UploadSession::claim_stale_by_id(
+                db_pool!(),
+                &session_id,
+                UploadStatus::Hashing,
+                stale_min,
+            )
+            .await?
+            {
+                continue;
+            }
+            match Self::after_upload(&session).await {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => error!("retry hash session {} fail, E: {}", session_id, e),
+            }
+        }
+        Ok(count)
     }
 }
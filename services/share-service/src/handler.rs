@@ -2,6 +2,7 @@ use crate::service::share_service::ShareService;
 use chrono::{Duration, Utc};
 use common::module::error::AppError;
 use common::module::share::{Share, ShareVo, ShareVoWithName};
+use common::util::cursor::CursorPage;
 use common::util::jwt::Claims;
 use common::util::result::{ResultCode, ResultData};
 use salvo::oapi::extract::{JsonBody, QueryParam};
@@ -9,8 +10,8 @@ use salvo::oapi::ToSchema;
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use common::db_pool;
-use common::module::item::Item;
+use common::{config, db_pool};
+use common::module::item::{ConflictPolicy, Item};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct GetShareDto {
@@ -32,6 +33,7 @@ struct SaveShareDto {
     code: Option<String>,
     logic_name: String,
     parent_id: Option<Uuid>,
+    conflict: Option<ConflictPolicy>,
 }
 
 #[endpoint(
@@ -111,16 +113,28 @@ pub async fn get_share_with_code(
 #[endpoint(
     status_codes(200),
     responses(
-        (status_code = 200, description = "get shares", body = ResultData<Vec<ShareVo>>),
+        (status_code = 200, description = "get shares, a CursorPage<Share> when a cursor is sent", body = ResultData<Vec<ShareVo>>),
     )
 )]
 pub async fn get_user_shares(
+    cursor: QueryParam<String, false>,
     res: &mut Response,
     depot: &mut Depot,
 ) -> Result<StatusCode, AppError> {
     let claims = depot
         .get::<Claims>("claims")
         .map_err(|_e| AppError::MissingToken)?;
+    // Keyset mode, "" for the first page then each reply's next_cursor
+    if let Some(cursor) = cursor.into_inner() {
+        let shares =
+            ShareService::get_user_shares_cursor(&claims.uid, &cursor, config!().page.size).await?;
+        res.render(Json(ResultData::<CursorPage<Share>>::new(
+            "Success",
+            Some(shares),
+            ResultCode::Success,
+        )));
+        return Ok(StatusCode::OK);
+    }
     let shares = ShareService::get_user_shares(&claims.uid).await?;
     res.render(Json(ResultData::<Vec<Share>>::new(
         "Success",
@@ -222,6 +236,7 @@ pub async fn save_share(
         save_share_dto.logic_name.clone(),
         &share.item_id.ok_or(AppError::ShareFileNotFound)?,
         &share.id,
+        save_share_dto.conflict.unwrap_or_default(),
     )
     .await?;
     res.render(Json(ResultData::<ShareVo>::new(
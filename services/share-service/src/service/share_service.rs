@@ -1,9 +1,14 @@
 use chrono::{DateTime, Utc};
 use common::db_pool;
 use common::module::error::AppError;
-use common::module::item::Item;
+use common::module::file::File;
+use common::module::item::{ConflictPolicy, Item, NameSlot};
+use common::module::item_version::ItemVersion;
 use common::module::share::Share;
-use common::util::path::FilePathInfo;
+use common::module::user::User;
+use common::util::cursor::CursorPage;
+use common::util::database::begin_tx;
+use tracing::error;
 use uuid::Uuid;
 
 pub struct ShareService {}
@@ -17,7 +22,7 @@ impl ShareService {
             .to_owned();
 
         Self::check_share_item(&share).await?;
-        
+
         Ok(share)
     }
 
@@ -28,13 +33,10 @@ impl ShareService {
             .ok_or(AppError::ShareFileNotFound)?
             .to_owned();
 
-
         Self::check_share_item(&share).await?;
-        
+
         match share.is_public {
-            Some(true) => {
-                Ok(share)
-            },
+            Some(true) => Ok(share),
             Some(false) => match &share.pickup_code {
                 Some(code) if code == pickup_code => Ok(share),
                 _ => Err(AppError::ShareCodeMismatched),
@@ -80,43 +82,65 @@ impl ShareService {
         Ok(shares)
     }
 
+    pub async fn get_user_shares_cursor(
+        user_id: &Uuid,
+        cursor: &str,
+        limit: u64,
+    ) -> Result<CursorPage<Share>, AppError> {
+        Share::select_after_by_userid(db_pool!(), cursor, limit, user_id).await
+    }
+
     pub async fn save_share_file(
         user_id: Uuid,
         parent_id: Option<Uuid>,
         logic_name: String,
         item_id: &Uuid,
         share_id: &Uuid,
+        conflict: ConflictPolicy,
     ) -> Result<(), AppError> {
         let item = Item::select_by_id(db_pool!(), item_id)
             .await?
             .first()
             .ok_or(AppError::ItemNotExists)?
             .to_owned();
-        let item = Item::new(user_id, item.file_id, parent_id, false, logic_name, true);
-        Item::insert(db_pool!(), &item).await?;
+        let file_id = item.file_id.ok_or(AppError::ItemNotExists)?;
+        let tx = begin_tx(db_pool!()).await?;
+        // Held until commit so GC cannot drop the file under the new item
+        let size = File::select_for_share(&tx, &file_id)
+            .await?
+            .size
+            .unwrap_or_default();
+        User::reserve_total_size_by_id(&tx, &user_id, &size).await?;
+        Item::lock_folder(&tx, &user_id, parent_id).await?;
+        Item::check_parent(&tx, &user_id, parent_id).await?;
+        // Overwriting saves the shared file as the next version of the existing one
+        let slot =
+            Item::resolve_name(&tx, &user_id, parent_id, &logic_name, conflict, None).await?;
+        let versioned = match slot {
+            NameSlot::Free(logic_name) => {
+                let item = Item::new(user_id, Some(file_id), parent_id, false, logic_name, true);
+                Item::insert(&tx, &item).await?;
+                None
+            }
+            NameSlot::Taken(existing) => {
+                let existing_id = existing
+                    .id
+                    .filter(|_| existing.is_folder == Some(false))
+                    .ok_or(AppError::ItemNameConflict)?;
+                ItemVersion::push(&tx, &user_id, &existing_id, &file_id).await?;
+                Some(existing_id)
+            }
+        };
+        tx.commit().await?;
         Share::add_once_save_times_by_id(db_pool!(), share_id).await?;
+        if let Some(existing_id) = versioned
+            && let Err(e) = ItemVersion::prune(db_pool!(), &user_id, &existing_id).await
+        {
+            error!("prune versions of {} fail, E: {}", existing_id, e);
+        }
         Ok(())
     }
 
-    pub async fn check_parent_id(
-        path: &FilePathInfo,
-        user_id: Uuid,
-    ) -> Result<Option<Uuid>, AppError> {
-        let parent_id = if path.parent != "/" {
-            let p_items =
-                Item::select_path_by_logic_name(db_pool!(), &path.parent, &user_id).await?;
-            let p_item = p_items.first().ok_or(AppError::PathOrNameError)?;
-            Some(
-                p_item
-                    .id
-                    .ok_or_else(|| AppError::InnerError("save-share-get_item_id".into()))?,
-            )
-        } else {
-            None
-        };
-        Ok(parent_id)
-    }
-    
     pub async fn check_share_item(share: &Share) -> Result<(), AppError> {
         let item_id = share.item_id.ok_or(AppError::ShareFileNotFound)?;
         let user_id = share.user_id.ok_or(AppError::ShareFileNotFound)?;
@@ -125,7 +149,7 @@ impl ShareService {
             None => {
                 Share::delete_by_id(db_pool!(), &share_id, &user_id).await?;
                 Err(AppError::ShareFileNotFound)
-            },
+            }
             Some(_) => Ok(()),
         }?;
         Ok(())
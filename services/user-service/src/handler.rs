@@ -4,7 +4,7 @@ use common::module::error::AppError;
 use common::module::user::{User, UserVo};
 use common::util::jwt::Claims;
 use common::util::result::{ResultCode, ResultData};
-use common::{config, minio_client};
+use common::{config, storage};
 use rbatis::Page;
 use salvo::oapi::extract::{JsonBody, PathParam};
 use salvo::oapi::ToSchema;
@@ -54,6 +54,11 @@ struct UsersDto {
     page: u64,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct RetentionDto {
+    retention: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct SizeDto {
     user_id: String,
@@ -297,12 +302,8 @@ pub async fn set_avatar(
             let path = ByteStream::from_path(file.path()).await;
             let path2 = path.map_err(|e| AppError::InnerError(e.to_string()))?;
 
-            minio_client!()
-                .put_object()
-                .bucket("avatar")
-                .key(key.clone())
-                .body(path2)
-                .send()
+            storage!()
+                .put(config!().minio.avatar_bucket.as_str(), key.as_str(), path2)
                 .await?;
 
             UserService::set_avatar(&claims.uid, &key).await?;
@@ -358,3 +359,26 @@ pub async fn set_user_max_size(
     )));
     Ok(StatusCode::OK)
 }
+
+#[endpoint(
+    status_codes(200),
+    responses(
+        (status_code = 200, description = "Set version retention", body = ResultData<String>),
+    )
+)]
+pub async fn set_version_retention(
+    retention_dto: JsonBody<RetentionDto>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<StatusCode, AppError> {
+    let claims = depot
+        .get::<Claims>("claims")
+        .map_err(|_e| AppError::MissingToken)?;
+    UserService::set_version_retention(&claims.uid, retention_dto.retention).await?;
+    res.render(Json(ResultData::<String>::new(
+        "Set success",
+        None,
+        ResultCode::Success,
+    )));
+    Ok(StatusCode::OK)
+}
@@ -12,6 +12,7 @@ pub fn all_router() -> Router {
             .push(Router::with_path("update-password").hoop(auth_middleware).post(change_password))
             .push(Router::with_path("check-token").hoop(auth_middleware).get(check_token))
             .push(Router::with_path("update-info").hoop(auth_middleware).post(update_userinfo))
+            .push(Router::with_path("update-version-retention").hoop(auth_middleware).post(set_version_retention))
             .push(Router::with_path("update-avatar").hoop(auth_middleware).put(set_avatar))
             .push(Router::with_path("delete-user{user_id}").hoop(auth_middleware).hoop(admin_middleware).post(delete_user))
             .push(Router::with_path("get-all-user").hoop(auth_middleware).hoop(admin_middleware).post(get_users))
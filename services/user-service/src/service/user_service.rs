@@ -1,6 +1,8 @@
 use argon2::{PasswordHash, PasswordVerifier};
 use common::module::error::AppError;
+use common::module::item_version::ItemVersion;
 use common::module::user::{User, UserVo};
+use common::util::database::begin_tx;
 use common::util::jwt;
 use common::{argon2_client, config, db_pool};
 use rbatis::{Page, PageRequest};
@@ -133,6 +135,18 @@ impl UserService {
         Ok(data)
     }
 
+    pub async fn set_version_retention(user_id: &Uuid, retention: u64) -> Result<(), AppError> {
+        if retention > config!().version.max_retention {
+            return Err(AppError::InvalidValue("retention".into()));
+        }
+        // A lower limit applies to the history already kept, not just to the next upload
+        let tx = begin_tx(db_pool!()).await?;
+        User::update_version_retention_by_id(&tx, user_id, &(retention as i32)).await?;
+        ItemVersion::prune_by_user(&tx, user_id, retention as usize).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub async fn set_user_max_size(user_id: &Uuid, max_size: &i64) -> Result<(), AppError> {
         User::update_max_size_by_id(db_pool!(), user_id, max_size).await?;
         Ok(())